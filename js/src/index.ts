@@ -21,10 +21,24 @@
 
 export type {
   NoteFields,
+  NoteType,
+  DeckCard,
+  DeckRenderResult,
   RenderOptions,
   RenderResult,
   StyleOptions,
   StyledRenderResult,
+  TemplateErrorDetails,
+  TemplateErrorKind,
+  SourcePos,
+  SourceSpan,
+  TagSpan,
+  TextNode,
+  FieldNode,
+  ConditionalNode,
+  TemplateNode,
+  TemplateAst,
+  TemplateAstError,
 } from './types.js';
 export { RenderError } from './types.js';
 
@@ -40,15 +54,32 @@ export {
 // Note: Web Component (AnkiCardPreview) is exported separately from 'anki-renderer/component'
 // to avoid loading DOM APIs in Node.js environments
 
-import type { RenderOptions, RenderResult, StyleOptions } from './types.js';
+import type {
+  DeckRenderResult,
+  NoteFields,
+  NoteType,
+  RenderOptions,
+  RenderResult,
+  StyleOptions,
+  TemplateAst,
+  TemplateErrorDetails,
+} from './types.js';
 import { RenderError } from './types.js';
 import { buildCss, wrapWithStyles } from './styles.js';
 
 // Used for StyledRenderResult return type
 import type { StyledRenderResult } from './types.js';
 
+// WASM compiled template handle
+interface WasmCompiledTemplate {
+  render(fields_json: string): string;
+  render_cloze(fields_json: string, card_ord: number, is_question: boolean): string;
+  free(): void;
+}
+
 // WASM module interface
 interface WasmModule {
+  CompiledTemplate: new (template: string) => WasmCompiledTemplate;
   render_template(template: string, fields_json: string): string;
   render_cloze_card(
     template: string,
@@ -56,7 +87,21 @@ interface WasmModule {
     card_ord: number,
     is_question: boolean
   ): string;
+  render_card(
+    front: string,
+    back: string,
+    fields_json: string,
+    card_ord: number,
+    typed_answer?: string
+  ): string;
+  render_deck(note_type_json: string, notes_json: string): string;
+  generate_cards(front_templates_json: string, fields_json: string): Uint32Array;
+  cloze_ordinals(fields_json: string): Uint32Array;
   count_cloze_cards(field_content: string): number;
+  compare_answer(expected: string, typed: string): string;
+  validate_template(template: string): Error[];
+  parse_template_ast(template: string): string;
+  rename_field(template: string, old_name: string, new_name: string): string;
   version(): string;
 }
 
@@ -96,6 +141,29 @@ async function loadNodeWasm(): Promise<WasmModule> {
   return wasm as unknown as WasmModule;
 }
 
+/**
+ * Wrap an error thrown by the WASM module in a RenderError, keeping the
+ * location details attached to template parse errors.
+ */
+function toRenderError(error: unknown): RenderError {
+  if (!(error instanceof Error)) {
+    return new RenderError(String(error));
+  }
+  const located = error as Error & Partial<TemplateErrorDetails>;
+  if (located.kind === undefined) {
+    return new RenderError(error.message);
+  }
+  return new RenderError(error.message, {
+    kind: located.kind,
+    offset: located.offset ?? 0,
+    line: located.line ?? 0,
+    column: located.column ?? 0,
+    snippet: located.snippet ?? '',
+    openLine: located.openLine,
+    openColumn: located.openColumn,
+  });
+}
+
 /**
  * Initialize the WASM module.
  *
@@ -150,17 +218,131 @@ export async function getVersion(): Promise<string> {
   return wasmModule!.version();
 }
 
+/**
+ * Check a template for errors without rendering it.
+ *
+ * Unlike rendering, which stops at the first problem, this reports every
+ * error in the template at once.
+ *
+ * @param template - Template string to check
+ * @returns One RenderError per problem, ordered by position; empty if valid
+ */
+export async function validateTemplate(template: string): Promise<RenderError[]> {
+  await initWasm();
+  return wasmModule!.validate_template(template).map(toRenderError);
+}
+
+/**
+ * Parse a template into its node tree.
+ *
+ * Parsing recovers from errors, so a broken template still yields the
+ * partial tree along with the errors found.
+ *
+ * @param template - Template string to parse
+ * @returns The versioned node tree, with source spans on every node
+ */
+export async function parseTemplate(template: string): Promise<TemplateAst> {
+  await initWasm();
+  return JSON.parse(wasmModule!.parse_template_ast(template)) as TemplateAst;
+}
+
+/**
+ * Rename a field in a template.
+ *
+ * Rewrites `{{Old}}`, `{{filter:Old}}`, `{{#Old}}`, `{{^Old}}` and
+ * `{{/Old}}` tags, leaving text untouched. When anything is renamed, the
+ * template's tags are written back in canonical `{{...}}` form.
+ *
+ * @param template - Template string
+ * @param oldName - The field's current name
+ * @param newName - The field's new name
+ * @returns The updated template; unchanged if it does not use the field
+ * @throws RenderError if the template cannot be parsed, or without
+ *   `details` if `newName` is not a valid field name
+ */
+export async function renameField(
+  template: string,
+  oldName: string,
+  newName: string
+): Promise<string> {
+  await initWasm();
+  try {
+    return wasmModule!.rename_field(template, oldName, newName);
+  } catch (error) {
+    throw toRenderError(error);
+  }
+}
+
+/**
+ * List the cards Anki would generate for a note.
+ *
+ * @param frontTemplates - The question template of each of the note type's card types, in order
+ * @param fields - The note's field values
+ * @returns For standard note types, the 0-based indices of the templates
+ *   whose front is not blank; for cloze note types, the cloze numbers in the
+ *   fields rendered with `{{cloze:...}}`. Empty if the note makes no cards.
+ * @throws RenderError if a template cannot be parsed
+ */
+export async function generateCards(
+  frontTemplates: string[],
+  fields: NoteFields
+): Promise<number[]> {
+  await initWasm();
+  try {
+    return Array.from(
+      wasmModule!.generate_cards(JSON.stringify(frontTemplates), JSON.stringify(fields))
+    );
+  } catch (error) {
+    throw toRenderError(error);
+  }
+}
+
+/**
+ * List the cloze numbers used in one or more fields.
+ *
+ * Each number is the ordinal of a card the note generates, so a note using
+ * only `c1` and `c5` yields `[1, 5]`: two cards, not five.
+ *
+ * @param fields - Field content, or several fields' contents, containing cloze syntax
+ * @returns The cloze numbers, sorted and without duplicates
+ */
+export async function clozeOrdinals(fields: string | string[]): Promise<number[]> {
+  await initWasm();
+  const contents = typeof fields === 'string' ? [fields] : fields;
+  return Array.from(wasmModule!.cloze_ordinals(JSON.stringify(contents)));
+}
+
 /**
  * Count the number of cloze cards a field generates.
  *
  * @param fieldContent - Field content containing cloze syntax (e.g., "{{c1::text}}")
- * @returns Number of unique cloze ordinals (number of cards)
+ * @returns Number of unique cloze ordinals (number of cards); the length of
+ *   {@link clozeOrdinals} for the field
  */
 export async function countClozeCards(fieldContent: string): Promise<number> {
   await initWasm();
   return wasmModule!.count_cloze_cards(fieldContent);
 }
 
+/**
+ * Grade a typed answer against the expected one, as Anki shows it on the
+ * answer side of a {{type:FieldName}} card.
+ *
+ * @param expected - The expected answer; HTML is reduced to plain text
+ * @param typed - What the user typed
+ * @returns `<code id=typeans>` HTML with `typeGood`, `typeBad` and
+ *   `typeMissed` spans marking the differences
+ *
+ * @example
+ * ```typescript
+ * const html = await compareAnswer("Paris", "Parix");
+ * ```
+ */
+export async function compareAnswer(expected: string, typed: string): Promise<string> {
+  await initWasm();
+  return wasmModule!.compare_answer(expected, typed);
+}
+
 /**
  * Render a card's front and back templates with the given fields.
  *
@@ -169,7 +351,9 @@ export async function countClozeCards(fieldContent: string): Promise<number> {
  * - Conditionals: {{#Field}}...{{/Field}} and {{^Field}}...{{/Field}}
  * - Filters: {{filter:FieldName}}
  * - Cloze deletions: {{cloze:FieldName}} with {{c1::text}}
- * - FrontSide replacement in back template
+ * - FrontSide replacement in back template, with the question's audio removed
+ * - Type-in answers: with `typedAnswer`, {{type:FieldName}} on the answer
+ *   side shows the typed answer graded against the field
  *
  * @param options - Rendering options
  * @returns Rendered question and answer HTML
@@ -196,33 +380,123 @@ export async function countClozeCards(fieldContent: string): Promise<number> {
 export async function renderCard(options: RenderOptions): Promise<RenderResult> {
   await initWasm();
 
-  const { front, back, fields, cardOrdinal = 0 } = options;
+  const { front, back, fields, cardOrdinal = 0, typedAnswer } = options;
 
   try {
-    let question: string;
-    let answer: string;
+    const json = wasmModule!.render_card(
+      front,
+      back,
+      JSON.stringify(fields),
+      cardOrdinal,
+      typedAnswer
+    );
+    return JSON.parse(json) as RenderResult;
+  } catch (error) {
+    throw toRenderError(error);
+  }
+}
 
-    if (cardOrdinal > 0) {
-      // Cloze card rendering
-      question = wasmModule!.render_cloze_card(front, JSON.stringify(fields), cardOrdinal, true);
-
-      // Add FrontSide to fields for back template rendering
-      const backFields = { ...fields, FrontSide: question };
-      answer = wasmModule!.render_cloze_card(back, JSON.stringify(backFields), cardOrdinal, false);
-    } else {
-      // Regular card rendering
-      question = wasmModule!.render_template(front, JSON.stringify(fields));
-
-      // Add FrontSide to fields for back template rendering
-      const backFields = { ...fields, FrontSide: question };
-      answer = wasmModule!.render_template(back, JSON.stringify(backFields));
+/**
+ * A template parsed once, for rendering many notes of the same note type.
+ *
+ * Create with {@link compileTemplate}. Call `free()` when done to release
+ * the WASM memory it holds.
+ */
+export class CompiledTemplate {
+  private readonly handle: WasmCompiledTemplate;
+
+  /** @internal Use {@link compileTemplate} */
+  constructor(handle: WasmCompiledTemplate) {
+    this.handle = handle;
+  }
+
+  /**
+   * Render the template with fields.
+   *
+   * @param fields - Field values
+   * @param cardOrdinal - Card ordinal for cloze (0 for non-cloze)
+   * @param isQuestion - Whether rendering question side (for cloze)
+   * @returns Rendered HTML
+   * @throws RenderError if rendering fails
+   */
+  render(fields: Record<string, string>, cardOrdinal = 0, isQuestion = false): string {
+    const fieldsJson = JSON.stringify(fields);
+    try {
+      if (cardOrdinal > 0) {
+        return this.handle.render_cloze(fieldsJson, cardOrdinal, isQuestion);
+      }
+      return this.handle.render(fieldsJson);
+    } catch (error) {
+      throw toRenderError(error);
     }
+  }
 
-    return { question, answer };
+  /**
+   * Release the WASM memory held by this template. It cannot be used
+   * afterwards.
+   */
+  free(): void {
+    this.handle.free();
+  }
+}
+
+/**
+ * Parse a template once for rendering many notes.
+ *
+ * Faster than calling {@link renderTemplate} repeatedly with the same
+ * template, which re-parses it on every call.
+ *
+ * @param template - Template string
+ * @returns The compiled template
+ * @throws RenderError if the template cannot be parsed
+ *
+ * @example
+ * ```typescript
+ * const front = await compileTemplate("{{Front}}");
+ * const questions = notes.map((fields) => front.render(fields));
+ * front.free();
+ * ```
+ */
+export async function compileTemplate(template: string): Promise<CompiledTemplate> {
+  await initWasm();
+  try {
+    return new CompiledTemplate(new wasmModule!.CompiledTemplate(template));
   } catch (error) {
-    throw new RenderError(
-      error instanceof Error ? error.message : String(error)
-    );
+    throw toRenderError(error);
+  }
+}
+
+/**
+ * Render every card of many notes of one note type in a single call.
+ *
+ * The templates are parsed once, and `{{FrontSide}}` is filled in natively.
+ * Standard notes produce one card each; cloze notes produce one card per
+ * cloze number.
+ *
+ * @param noteType - The note type's front and back templates and CSS
+ * @param notes - Field values of each note
+ * @returns Every generated card, plus the note type's CSS
+ * @throws RenderError if a template cannot be parsed
+ *
+ * @example
+ * ```typescript
+ * const { cards } = await renderDeck(
+ *   { front: "{{cloze:Text}}", back: "{{cloze:Text}}" },
+ *   [{ Text: "{{c1::Paris}} and {{c2::Rome}}" }]
+ * );
+ * // cards: [{ noteIndex: 0, ord: 1, ... }, { noteIndex: 0, ord: 2, ... }]
+ * ```
+ */
+export async function renderDeck(
+  noteType: NoteType,
+  notes: NoteFields[]
+): Promise<DeckRenderResult> {
+  await initWasm();
+  try {
+    const json = wasmModule!.render_deck(JSON.stringify(noteType), JSON.stringify(notes));
+    return JSON.parse(json) as DeckRenderResult;
+  } catch (error) {
+    throw toRenderError(error);
   }
 }
 
@@ -264,15 +538,15 @@ export async function renderStyledCard(
   options: StyledRenderOptions
 ): Promise<StyledRenderResult> {
   // First render the raw content
-  const { question, answer } = await renderCard(options);
+  const rendered = await renderCard(options);
+  const { question, answer } = rendered;
 
   // Build CSS and wrap with styles
   const styleOpts = options.style || {};
   const css = buildCss(styleOpts);
 
   return {
-    question,
-    answer,
+    ...rendered,
     styledQuestion: wrapWithStyles(question, css, styleOpts.nightMode),
     styledAnswer: wrapWithStyles(answer, css, styleOpts.nightMode),
   };
@@ -306,8 +580,6 @@ export async function renderTemplate(
     }
     return wasmModule!.render_template(template, fieldsJson);
   } catch (error) {
-    throw new RenderError(
-      error instanceof Error ? error.message : String(error)
-    );
+    throw toRenderError(error);
   }
 }
@@ -32,6 +32,18 @@ export const DEFAULT_ANKI_CSS = `
   cursor: pointer;
 }
 
+.typeGood {
+  background-color: #afa;
+}
+
+.typeBad {
+  background-color: #faa;
+}
+
+.typeMissed {
+  background-color: #ccc;
+}
+
 img {
   max-width: 100%;
   height: auto;
@@ -62,6 +74,18 @@ export const NIGHT_MODE_CSS = `
   color: #ccc;
 }
 
+.typeGood {
+  background-color: #3a5f3a;
+}
+
+.typeBad {
+  background-color: #7a3a3a;
+}
+
+.typeMissed {
+  background-color: #555;
+}
+
 a {
   color: #5cb3ff;
 }
@@ -43,6 +43,12 @@ export interface RenderOptions {
    * @default 0
    */
   cardOrdinal?: number;
+  /**
+   * What the user typed into the {{type:Field}} input. When given, the
+   * answer side shows it compared with the expected answer, in Anki's
+   * `<code id=typeans>` markup, instead of the input.
+   */
+  typedAnswer?: string;
 }
 
 /**
@@ -53,6 +59,46 @@ export interface RenderResult {
   question: string;
   /** Rendered HTML for the answer (back) side */
   answer: string;
+  /**
+   * Whether the question is blank by Anki's definition, in which case Anki
+   * would neither create nor show the card: no non-empty field is
+   * substituted on the front (fields holding only whitespace, `&nbsp;`,
+   * `<br>` or empty `<div>`s count as empty), or for cloze cards, no
+   * deletion has the card's number.
+   */
+  questionIsEmpty: boolean;
+}
+
+/**
+ * A note type's card templates, for batch rendering.
+ */
+export interface NoteType {
+  /** Question (front) template */
+  front: string;
+  /** Answer (back) template */
+  back: string;
+  /** Styling shared by all cards */
+  css?: string;
+}
+
+/**
+ * One card produced by batch rendering.
+ */
+export interface DeckCard extends RenderResult {
+  /** Index of the card's note in the input array */
+  noteIndex: number;
+  /** Card ordinal: 0 for standard cards, the cloze number for cloze cards */
+  ord: number;
+}
+
+/**
+ * Result of rendering many notes at once.
+ */
+export interface DeckRenderResult {
+  /** The note type's CSS */
+  css: string;
+  /** Every generated card, in note order then ordinal order */
+  cards: DeckCard[];
 }
 
 /**
@@ -65,16 +111,144 @@ export interface StyledRenderResult extends RenderResult {
   styledAnswer: string;
 }
 
+/**
+ * Category of a template parse error.
+ */
+export type TemplateErrorKind =
+  | 'syntax'
+  | 'mismatchedClose'
+  | 'unopenedClose'
+  | 'unclosedBlock';
+
+/**
+ * Location details of a template parse error.
+ */
+export interface TemplateErrorDetails {
+  /** Error category */
+  kind: TemplateErrorKind;
+  /** Byte offset of the error in the template */
+  offset: number;
+  /** Line number (1-indexed) */
+  line: number;
+  /** Column in characters (1-indexed) */
+  column: number;
+  /** The offending source text, e.g. the tag at the error location */
+  snippet: string;
+  /** Line of the related open tag, for mismatched and unclosed blocks */
+  openLine?: number;
+  /** Column of the related open tag, for mismatched and unclosed blocks */
+  openColumn?: number;
+}
+
 /**
  * Error thrown when card rendering fails.
+ *
+ * When the failure is a template parse error, `details` locates it.
  */
 export class RenderError extends Error {
-  constructor(message: string) {
+  readonly details?: TemplateErrorDetails;
+
+  constructor(message: string, details?: TemplateErrorDetails) {
     super(message);
     this.name = 'RenderError';
+    this.details = details;
     // Maintain proper stack trace in V8 environments
     if (Error.captureStackTrace) {
       Error.captureStackTrace(this, RenderError);
     }
   }
 }
+
+/**
+ * A position in template source.
+ */
+export interface SourcePos {
+  /** Byte offset from the start of the template */
+  offset: number;
+  /** Line number (1-indexed) */
+  line: number;
+  /** Column in characters (1-indexed) */
+  column: number;
+}
+
+/**
+ * A range of template source.
+ */
+export interface SourceSpan {
+  start: SourcePos;
+  end: SourcePos;
+}
+
+/**
+ * Location of a tag: the whole tag, and the field name within it.
+ */
+export interface TagSpan {
+  tag: SourceSpan;
+  name: SourceSpan;
+}
+
+/**
+ * Literal text between tags.
+ */
+export interface TextNode {
+  kind: 'text';
+  text: string;
+  span: SourceSpan;
+}
+
+/**
+ * A field substitution such as `{{text:Front}}`.
+ */
+export interface FieldNode {
+  kind: 'field';
+  name: string;
+  /** Filter names in source order, e.g. `['text']` */
+  filters: string[];
+  span: TagSpan;
+  /** Location of each filter name, parallel to `filters` */
+  filterSpans: SourceSpan[];
+}
+
+/**
+ * A `{{#Field}}` or `{{^Field}}` block.
+ */
+export interface ConditionalNode {
+  kind: 'conditional';
+  field: string;
+  /** True for `{{^Field}}` blocks */
+  isNegative: boolean;
+  children: TemplateNode[];
+  open: TagSpan;
+  /** Empty span at the close point if the block was closed implicitly */
+  close: TagSpan;
+}
+
+/**
+ * A node of a parsed template.
+ */
+export type TemplateNode = TextNode | FieldNode | ConditionalNode;
+
+/**
+ * A template parse error, as listed in a {@link TemplateAst}.
+ */
+export interface TemplateAstError {
+  kind: TemplateErrorKind;
+  message: string;
+  offset: number;
+  line: number;
+  column: number;
+  snippet: string;
+  /** Position of the related open tag, for block errors */
+  openPos: SourcePos | null;
+}
+
+/**
+ * A parsed template.
+ */
+export interface TemplateAst {
+  /** Schema version, incremented on incompatible changes */
+  version: 1;
+  nodes: TemplateNode[];
+  /** Errors found while parsing; `nodes` is the partial tree if non-empty */
+  errors: TemplateAstError[];
+}
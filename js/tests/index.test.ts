@@ -7,6 +7,14 @@ import {
   renderTemplate,
   renderStyledCard,
   countClozeCards,
+  clozeOrdinals,
+  compareAnswer,
+  validateTemplate,
+  parseTemplate,
+  renameField,
+  compileTemplate,
+  renderDeck,
+  generateCards,
   getVersion,
   initWasm,
   isInitialized,
@@ -67,6 +75,26 @@ describe('anki-renderer', () => {
       expect(result.answer).toBe('<b>Bold Question</b><hr>Answer');
     });
 
+    it('should flag a blank front', async () => {
+      const options = { front: 'Q: {{Front}}', back: '{{Back}}' };
+      const blank = await renderCard({ ...options, fields: { Front: '<br> ', Back: 'A' } });
+      const filled = await renderCard({ ...options, fields: { Front: 'x', Back: 'A' } });
+
+      expect(blank.questionIsEmpty).toBe(true);
+      expect(filled.questionIsEmpty).toBe(false);
+    });
+
+    it('should strip question audio from FrontSide', async () => {
+      const result = await renderCard({
+        front: '{{Front}}',
+        back: '{{FrontSide}}<hr id=answer>{{Back}}',
+        fields: { Front: 'Hund[sound:hund.mp3]', Back: 'dog' },
+      });
+
+      expect(result.question).toBe('Hund[sound:hund.mp3]');
+      expect(result.answer).toBe('Hund<hr id=answer>dog');
+    });
+
     it('should handle conditionals', async () => {
       const result = await renderCard({
         front: '{{#Extra}}Has extra: {{Extra}}{{/Extra}}{{^Extra}}No extra{{/Extra}}',
@@ -110,7 +138,10 @@ describe('anki-renderer', () => {
 
       expect(result.question).toContain('[...]');
       expect(result.question).toContain('France'); // c2 should be revealed
-      expect(result.question).not.toContain('Paris'); // c1 should be hidden
+      // c1 should be hidden; its text is only kept in the data-cloze attribute
+      expect(result.question.replace(/<[^>]*>/g, '')).not.toContain('Paris');
+      expect(result.question).toContain('data-cloze="Paris"');
+      expect(result.question).toContain('class="cloze-inactive" data-ordinal="2"');
     });
 
     it('should render cloze answer side', async () => {
@@ -135,6 +166,70 @@ describe('anki-renderer', () => {
 
       expect(result.question).toContain('[capital city]');
     });
+
+    it('should render only the active deletions with cloze-only', async () => {
+      const result = await renderCard({
+        front: '{{cloze:Text}}',
+        back: '{{cloze-only:Text}}',
+        fields: { Text: '{{c1::Paris}} is the capital of {{c2::France}}, not {{c1::Lyon}}' },
+        cardOrdinal: 1,
+      });
+
+      expect(result.answer).toBe('Paris, Lyon');
+    });
+  });
+
+  describe('type-in answers', () => {
+    it('should show the input on both sides without a typed answer', async () => {
+      const result = await renderCard({
+        front: '{{Front}} {{type:Back}}',
+        back: '{{FrontSide}}<hr id=answer>{{Back}}',
+        fields: { Front: 'Capital of France?', Back: 'Paris' },
+      });
+
+      expect(result.question).toContain('id="typeans"');
+      expect(result.answer).toContain('id="typeans"');
+      expect(result.answer).not.toContain('<code');
+    });
+
+    it('should grade the typed answer on the answer side', async () => {
+      const result = await renderCard({
+        front: '{{Front}} {{type:Back}}',
+        back: '{{FrontSide}}<hr id=answer>{{Back}}',
+        fields: { Front: 'Capital of France?', Back: 'Paris' },
+        typedAnswer: 'Parix',
+      });
+
+      expect(result.question).toContain('<input');
+      expect(result.answer).not.toContain('<input');
+      expect(result.answer).toContain('<code id=typeans>');
+      expect(result.answer).toContain('<span class=typeBad>x</span>');
+      expect(result.answer).toContain('<span class=typeMissed>s</span>');
+    });
+
+    it('should grade cloze type-in answers against the active deletion', async () => {
+      const result = await renderCard({
+        front: '{{cloze:Text}}<br>{{type:cloze:Text}}',
+        back: '{{cloze:Text}}<br>{{type:cloze:Text}}',
+        fields: { Text: '{{c1::Paris}} is the capital of {{c2::France}}' },
+        cardOrdinal: 2,
+        typedAnswer: 'France',
+      });
+
+      expect(result.answer).toContain(
+        '<code id=typeans><span class=typeGood>France</span></code>'
+      );
+    });
+
+    it('should compare answers directly', async () => {
+      expect(await compareAnswer('<b>Paris</b>', 'Paris')).toBe(
+        '<code id=typeans><span class=typeGood>Paris</span></code>'
+      );
+      expect(await compareAnswer('cat', 'cats')).toBe(
+        '<code id=typeans><span class=typeGood>cat</span><span class=typeBad>s</span>' +
+          '<br><span id=typearrow>&darr;</span><br><span class=typeGood>cat</span></code>'
+      );
+    });
   });
 
   describe('countClozeCards', () => {
@@ -154,6 +249,25 @@ describe('anki-renderer', () => {
       const count = await countClozeCards('{{c1::one}} and {{c1::another}}');
       expect(count).toBe(1);
     });
+
+    it('should count only the ordinals used', async () => {
+      const count = await countClozeCards('{{c1::one}} and {{c5::five}}');
+      expect(count).toBe(2);
+    });
+  });
+
+  describe('clozeOrdinals', () => {
+    it('should return sorted unique ordinals', async () => {
+      expect(await clozeOrdinals('{{c5::a}} {{c1::b}} {{c5::c}}')).toEqual([1, 5]);
+    });
+
+    it('should combine several fields', async () => {
+      expect(await clozeOrdinals(['{{c3::a}}', 'plain', '{{c1::b}}'])).toEqual([1, 3]);
+    });
+
+    it('should return an empty array for non-cloze content', async () => {
+      expect(await clozeOrdinals('Just plain text')).toEqual([]);
+    });
   });
 
   describe('renderTemplate', () => {
@@ -173,6 +287,133 @@ describe('anki-renderer', () => {
     });
   });
 
+  describe('validateTemplate', () => {
+    it('should return no errors for a valid template', async () => {
+      expect(await validateTemplate('{{#A}}{{A}}{{/A}}')).toEqual([]);
+    });
+
+    it('should report every error at once', async () => {
+      const errors = await validateTemplate('{{Front}\n{{/Back}}\n{{#Extra}}');
+      expect(errors.map((e) => e.details?.kind)).toEqual([
+        'syntax',
+        'unopenedClose',
+        'unclosedBlock',
+      ]);
+      expect(errors.map((e) => e.details?.line)).toEqual([1, 2, 3]);
+    });
+  });
+
+  describe('parseTemplate', () => {
+    it('should return the versioned node tree', async () => {
+      const ast = await parseTemplate('{{#Extra}}{{text:Extra}}{{/Extra}}');
+      expect(ast.version).toBe(1);
+      expect(ast.errors).toEqual([]);
+      const block = ast.nodes[0];
+      expect(block.kind).toBe('conditional');
+      if (block.kind !== 'conditional') return;
+      expect(block.field).toBe('Extra');
+      expect(block.isNegative).toBe(false);
+      expect(block.children[0]).toMatchObject({
+        kind: 'field',
+        name: 'Extra',
+        filters: ['text'],
+      });
+      expect(block.open.tag.start).toEqual({ offset: 0, line: 1, column: 1 });
+    });
+
+    it('should include errors alongside the partial tree', async () => {
+      const ast = await parseTemplate('{{Front}}{{/Back}}');
+      expect(ast.nodes[0]).toMatchObject({ kind: 'field', name: 'Front' });
+      expect(ast.errors.map((e) => e.kind)).toEqual(['unopenedClose']);
+    });
+  });
+
+  describe('renameField', () => {
+    it('should rename tags but not text', async () => {
+      const renamed = await renameField(
+        'Old: {{Old}} {{#Old}}{{text:Old}}{{/Old}}',
+        'Old',
+        'New'
+      );
+      expect(renamed).toBe('Old: {{New}} {{#New}}{{text:New}}{{/New}}');
+    });
+
+    it('should throw RenderError for an invalid template', async () => {
+      await expect(renameField('{{#Old}}', 'Old', 'New')).rejects.toThrow(RenderError);
+    });
+
+    it('should throw RenderError for an invalid new name', async () => {
+      for (const newName of ['', 'a:b', 'x}}y', '#x']) {
+        const error = await renameField('{{Old}}', 'Old', newName).catch((e) => e);
+        expect(error).toBeInstanceOf(RenderError);
+        expect(error.message).toBe(`Invalid field name: ${JSON.stringify(newName)}`);
+        expect(error.details).toBeUndefined();
+      }
+    });
+  });
+
+  describe('compileTemplate', () => {
+    it('should render many notes with one parse', async () => {
+      const compiled = await compileTemplate('{{Front}}{{#Back}}/{{Back}}{{/Back}}');
+      expect(compiled.render({ Front: 'a' })).toBe('a');
+      expect(compiled.render({ Front: 'b', Back: 'c' })).toBe('b/c');
+      compiled.free();
+    });
+
+    it('should render cloze cards', async () => {
+      const compiled = await compileTemplate('{{cloze:Text}}');
+      const fields = { Text: '{{c1::Paris}} and {{c2::Rome}}' };
+      expect(compiled.render(fields, 1, true)).toBe(
+        await renderTemplate('{{cloze:Text}}', fields, 1, true)
+      );
+      compiled.free();
+    });
+
+    it('should throw RenderError for an invalid template', async () => {
+      await expect(compileTemplate('{{#A}}')).rejects.toThrow(RenderError);
+    });
+  });
+
+  describe('renderDeck', () => {
+    it('should render every card of every note', async () => {
+      const result = await renderDeck(
+        { front: '{{cloze:Text}}', back: '{{FrontSide}}<hr>{{Extra}}', css: '.card {}' },
+        [{ Text: '{{c1::a}} {{c2::b}}', Extra: 'x' }, { Text: '{{c1::c}}', Extra: 'y' }]
+      );
+      expect(result.css).toBe('.card {}');
+      expect(result.cards.map((c) => [c.noteIndex, c.ord])).toEqual([
+        [0, 1],
+        [0, 2],
+        [1, 1],
+      ]);
+      expect(result.cards[2].answer).toBe(`${result.cards[2].question}<hr>y`);
+    });
+
+    it('should render one card per standard note', async () => {
+      const { cards } = await renderDeck({ front: '{{Front}}', back: '{{Back}}' }, [
+        { Front: 'Q', Back: 'A' },
+      ]);
+      expect(cards).toEqual([
+        { noteIndex: 0, ord: 0, question: 'Q', answer: 'A', questionIsEmpty: false },
+      ]);
+    });
+  });
+
+  describe('generateCards', () => {
+    it('should list templates with a non-blank front', async () => {
+      const fronts = ['{{Front}}', '{{#Reverse}}{{Back}}{{/Reverse}}'];
+      expect(await generateCards(fronts, { Front: 'F', Back: 'B' })).toEqual([0]);
+      expect(await generateCards(fronts, { Front: 'F', Back: 'B', Reverse: 'y' })).toEqual([
+        0, 1,
+      ]);
+    });
+
+    it('should list cloze numbers for cloze note types', async () => {
+      const ords = await generateCards(['{{cloze:Text}}'], { Text: '{{c1::a}} {{c2::b}}' });
+      expect(ords).toEqual([1, 2]);
+    });
+  });
+
   describe('RenderError', () => {
     it('should be exported', () => {
       expect(RenderError).toBeDefined();
@@ -180,6 +421,22 @@ describe('anki-renderer', () => {
       expect(error.name).toBe('RenderError');
       expect(error.message).toBe('test error');
     });
+
+    it('should expose template error location', async () => {
+      await expect(
+        renderTemplate('{{#Front}}\n{{/Back}}', {})
+      ).rejects.toMatchObject({
+        name: 'RenderError',
+        details: {
+          kind: 'mismatchedClose',
+          line: 2,
+          column: 1,
+          snippet: '{{/Back}}',
+          openLine: 1,
+          openColumn: 1,
+        },
+      });
+    });
   });
 
   describe('filters', () => {
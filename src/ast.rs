@@ -0,0 +1,117 @@
+use crate::error::TemplateError;
+use crate::template::{self, TemplateNode};
+use serde::Serialize;
+
+/// Version of the JSON AST schema. Bump when the shape of the output
+/// changes in a way consumers must handle.
+pub const AST_SCHEMA_VERSION: u32 = 1;
+
+/// A parsed template as exposed to JavaScript
+#[derive(Debug, Serialize)]
+struct AstDocument<'a> {
+    version: u32,
+    nodes: &'a [TemplateNode],
+    errors: &'a [TemplateError],
+}
+
+/// Parse a template and serialize its node tree as versioned JSON.
+///
+/// The template is parsed in recovery mode, so a template with errors still
+/// yields the partial tree, with the problems listed under `errors`.
+///
+/// ```json
+/// {
+///   "version": 1,
+///   "nodes": [
+///     { "kind": "text", "text": "Q: ", "span": { "start": {...}, "end": {...} } },
+///     { "kind": "field", "name": "Front", "filters": ["text"],
+///       "span": { "tag": {...}, "name": {...} }, "filterSpans": [{...}] },
+///     { "kind": "conditional", "field": "Extra", "isNegative": false,
+///       "children": [...], "open": { "tag": {...}, "name": {...} },
+///       "close": { "tag": {...}, "name": {...} } }
+///   ],
+///   "errors": []
+/// }
+/// ```
+///
+/// Positions are `{ "offset", "line", "column" }` with byte offsets and
+/// 1-indexed lines and character columns.
+pub fn template_ast_json(template: &str) -> String {
+    let (nodes, errors) = template::parse_template_recovering(template);
+    let document = AstDocument {
+        version: AST_SCHEMA_VERSION,
+        nodes: &nodes,
+        errors: &errors,
+    };
+    // Serializing plain structs of strings and numbers cannot fail
+    serde_json::to_string(&document).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn parse(template: &str) -> Value {
+        serde_json::from_str(&template_ast_json(template)).unwrap()
+    }
+
+    #[test]
+    fn test_document_is_versioned() {
+        let doc = parse("");
+        assert_eq!(doc["version"], json!(AST_SCHEMA_VERSION));
+        assert_eq!(doc["nodes"], json!([]));
+        assert_eq!(doc["errors"], json!([]));
+    }
+
+    #[test]
+    fn test_field_node_json() {
+        let doc = parse("{{hint:Front}}");
+        assert_eq!(
+            doc["nodes"][0],
+            json!({
+                "kind": "field",
+                "name": "Front",
+                "filters": ["hint"],
+                "span": {
+                    "tag": {
+                        "start": { "offset": 0, "line": 1, "column": 1 },
+                        "end": { "offset": 14, "line": 1, "column": 15 }
+                    },
+                    "name": {
+                        "start": { "offset": 7, "line": 1, "column": 8 },
+                        "end": { "offset": 12, "line": 1, "column": 13 }
+                    }
+                },
+                "filterSpans": [{
+                    "start": { "offset": 2, "line": 1, "column": 3 },
+                    "end": { "offset": 6, "line": 1, "column": 7 }
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_conditional_node_json() {
+        let doc = parse("{{^Extra}}none{{/Extra}}");
+        let node = &doc["nodes"][0];
+        assert_eq!(node["kind"], "conditional");
+        assert_eq!(node["field"], "Extra");
+        assert_eq!(node["isNegative"], true);
+        assert_eq!(node["children"][0]["kind"], "text");
+        assert_eq!(node["children"][0]["text"], "none");
+        assert_eq!(node["open"]["name"]["start"]["offset"], 3);
+        assert_eq!(node["close"]["tag"]["start"]["offset"], 14);
+    }
+
+    #[test]
+    fn test_errors_included_with_partial_tree() {
+        let doc = parse("{{Front}}{{/Back}}");
+        assert_eq!(doc["nodes"][0]["name"], "Front");
+        assert_eq!(doc["errors"][0]["kind"], "unopenedClose");
+        assert_eq!(doc["errors"][0]["line"], 1);
+        assert_eq!(doc["errors"][0]["column"], 10);
+        assert_eq!(doc["errors"][0]["snippet"], "{{/Back}}");
+        assert_eq!(doc["errors"][0]["openPos"], Value::Null);
+    }
+}
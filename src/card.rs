@@ -0,0 +1,624 @@
+use crate::cloze;
+use crate::error::TemplateError;
+use crate::template::{field_is_empty, CompiledTemplate, TemplateNode};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+
+/// Audio and text-to-speech tags: [sound:file.mp3] and [anki:tts]...[/anki:tts]
+static AV_TAG_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)\[sound:.+?\]|\[anki:tts[^\]]*\].*?\[/anki:tts\]").unwrap());
+
+/// The `<hr id=answer>` separator Anki's reviewer scrolls to on the answer
+/// side, with any attribute quoting
+static ANSWER_ANCHOR_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)<hr\b[^>]*\bid\s*=\s*["']?answer\b["']?[^>]*>"#).unwrap());
+
+/// Fields filled in by the renderer rather than the note, which do not
+/// make a card non-empty
+const SPECIAL_FIELDS: [&str; 4] = ["FrontSide", "Tags", "Deck", "Card"];
+
+/// A note type with a single card template
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NoteType {
+    /// Question (front) template
+    pub front: String,
+    /// Answer (back) template
+    pub back: String,
+    /// Styling shared by all cards
+    #[serde(default)]
+    pub css: String,
+}
+
+/// Both sides of a rendered card
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderedCard {
+    pub question: String,
+    pub answer: String,
+    /// Whether the question is blank by Anki's definition, in which case
+    /// Anki would neither create nor show the card: for standard cards, no
+    /// non-empty note field is substituted on the front; for cloze cards,
+    /// no deletion has the card's number
+    pub question_is_empty: bool,
+}
+
+impl RenderedCard {
+    /// The part of the answer after its `<hr id=answer>` separator, i.e.
+    /// without the repeated question; the whole answer if there is none
+    pub fn answer_only(&self) -> &str {
+        match ANSWER_ANCHOR_REGEX.find(&self.answer) {
+            Some(anchor) => &self.answer[anchor.end()..],
+            None => &self.answer,
+        }
+    }
+}
+
+/// A note type's front and back templates, parsed once
+#[derive(Debug, Clone)]
+pub struct CardTemplates {
+    front: CompiledTemplate,
+    back: CompiledTemplate,
+    /// Fields the front template renders with the `cloze` filter; empty for
+    /// standard note types
+    cloze_fields: Vec<String>,
+}
+
+impl CardTemplates {
+    /// Parse both templates, failing on the first error in either
+    pub fn new(front: &str, back: &str) -> Result<Self, TemplateError> {
+        let front = CompiledTemplate::new(front)?;
+        let back = CompiledTemplate::new(back)?;
+        let cloze_fields = cloze_fields(front.nodes());
+        Ok(CardTemplates {
+            front,
+            back,
+            cloze_fields,
+        })
+    }
+
+    /// Whether this is a cloze note type, i.e. the front uses `{{cloze:...}}`
+    pub fn is_cloze(&self) -> bool {
+        !self.cloze_fields.is_empty()
+    }
+
+    /// Ordinals of the cards to render for a note: `[0]` for standard note
+    /// types, and the cloze numbers used for cloze note types. A cloze note
+    /// without deletions still has card 1, so that it can be shown as blank.
+    pub fn card_ords(&self, fields: &HashMap<String, String>) -> Vec<u32> {
+        if !self.is_cloze() {
+            return vec![0];
+        }
+        let ords = cloze_ords(&self.cloze_fields, fields);
+        if ords.is_empty() {
+            vec![1]
+        } else {
+            ords
+        }
+    }
+
+    /// Whether the front of card `ord` is blank, as for
+    /// [`RenderedCard::question_is_empty`]
+    pub fn question_is_empty(&self, fields: &HashMap<String, String>, ord: u32) -> bool {
+        if self.is_cloze() {
+            return !cloze_ords(&self.cloze_fields, fields).contains(&ord);
+        }
+        template_is_empty(self.front.nodes(), &nonempty_fields(fields))
+    }
+
+    /// Render both sides of card `ord` (0 for standard cards, the 1-indexed
+    /// cloze number for cloze cards). The answer side sees the rendered
+    /// question as `{{FrontSide}}`, without its audio so that it is not
+    /// played a second time.
+    pub fn render(&self, fields: &HashMap<String, String>, ord: u32) -> RenderedCard {
+        self.render_answered(fields, ord, None)
+    }
+
+    /// Render both sides of card `ord` after the user typed `typed_answer`.
+    ///
+    /// The question still shows the type-in input, while on the answer side
+    /// each `{{type:...}}` field, including those from `{{FrontSide}}`,
+    /// shows the typed answer compared with the expected one.
+    pub fn render_with_typed_answer(
+        &self,
+        fields: &HashMap<String, String>,
+        ord: u32,
+        typed_answer: &str,
+    ) -> RenderedCard {
+        self.render_answered(fields, ord, Some(typed_answer))
+    }
+
+    fn render_answered(
+        &self,
+        fields: &HashMap<String, String>,
+        ord: u32,
+        typed_answer: Option<&str>,
+    ) -> RenderedCard {
+        let question = self.render_side(&self.front, fields, ord, true, None);
+        let front_side = match typed_answer {
+            Some(_) => self.render_side(&self.front, fields, ord, true, typed_answer),
+            None => question.clone(),
+        };
+        let mut back_fields = fields.clone();
+        back_fields.insert("FrontSide".to_string(), strip_av_tags(&front_side));
+        let answer = self.render_side(&self.back, &back_fields, ord, false, typed_answer);
+        RenderedCard {
+            question,
+            answer,
+            question_is_empty: self.question_is_empty(fields, ord),
+        }
+    }
+
+    fn render_side(
+        &self,
+        template: &CompiledTemplate,
+        fields: &HashMap<String, String>,
+        ord: u32,
+        is_question: bool,
+        typed_answer: Option<&str>,
+    ) -> String {
+        if let Some(typed) = typed_answer {
+            template.render_with_typed_answer(fields, ord, is_question, typed)
+        } else if ord > 0 {
+            template.render_cloze(fields, ord, is_question)
+        } else {
+            template.render(fields)
+        }
+    }
+}
+
+/// Render both sides of one card.
+///
+/// `ord` is 0 for standard cards and the 1-indexed cloze number for cloze
+/// cards. See [`CardTemplates::render`].
+pub fn render_card(
+    front: &str,
+    back: &str,
+    fields: &HashMap<String, String>,
+    ord: u32,
+) -> Result<RenderedCard, TemplateError> {
+    Ok(CardTemplates::new(front, back)?.render(fields, ord))
+}
+
+/// Remove audio and text-to-speech tags, as Anki does when inserting the
+/// question into the answer
+fn strip_av_tags(html: &str) -> String {
+    AV_TAG_REGEX.replace_all(html, "").into_owned()
+}
+
+/// Whether rendering `nodes` substitutes none of the `nonempty` fields.
+///
+/// Like Anki, text never makes a template non-empty, `{{#Field}}` blocks
+/// count only when the field is non-empty, and `{{^Field}}` blocks are
+/// always searched.
+fn template_is_empty(nodes: &[TemplateNode], nonempty: &HashSet<&str>) -> bool {
+    nodes.iter().all(|node| match node {
+        TemplateNode::Text { .. } => true,
+        TemplateNode::Field { name, .. } => !nonempty.contains(name.as_str()),
+        TemplateNode::Conditional {
+            field,
+            children,
+            is_negative,
+            ..
+        } => {
+            (!is_negative && !nonempty.contains(field.as_str()))
+                || template_is_empty(children, nonempty)
+        }
+    })
+}
+
+/// Names of the fields rendered with the `cloze` filter, in template order
+fn cloze_fields(nodes: &[TemplateNode]) -> Vec<String> {
+    let mut names = Vec::new();
+    collect_cloze_fields(nodes, &mut names);
+    names
+}
+
+fn collect_cloze_fields(nodes: &[TemplateNode], out: &mut Vec<String>) {
+    for node in nodes {
+        match node {
+            TemplateNode::Text { .. } => {}
+            TemplateNode::Field { name, filters, .. } => {
+                if filters.iter().any(|f| f == "cloze") && !out.contains(name) {
+                    out.push(name.clone());
+                }
+            }
+            TemplateNode::Conditional { children, .. } => collect_cloze_fields(children, out),
+        }
+    }
+}
+
+/// Cloze numbers in the named fields, sorted and without duplicates
+fn cloze_ords(cloze_fields: &[String], fields: &HashMap<String, String>) -> Vec<u32> {
+    cloze::cloze_ordinals(
+        cloze_fields
+            .iter()
+            .filter_map(|name| fields.get(name))
+            .map(String::as_str),
+    )
+}
+
+/// Names of the note fields with content
+fn nonempty_fields(fields: &HashMap<String, String>) -> HashSet<&str> {
+    fields
+        .iter()
+        .filter(|(name, value)| !SPECIAL_FIELDS.contains(&name.as_str()) && !field_is_empty(value))
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+/// Ordinals of the cards Anki would generate for a note.
+///
+/// `fronts` are the question templates of the note type's card types, in
+/// order. For a standard note type the result lists the (0-based) indices
+/// of the templates whose front is not blank for the note, as for
+/// [`RenderedCard::question_is_empty`]. For a cloze note type, detected by
+/// the first template rendering a field with `{{cloze:...}}`, it lists the
+/// (1-indexed) cloze numbers found in the fields so rendered. A note that
+/// generates no cards gets an empty list.
+pub fn generate_cards(fronts: &[CompiledTemplate], fields: &HashMap<String, String>) -> Vec<u32> {
+    let Some(first) = fronts.first() else {
+        return Vec::new();
+    };
+    let cloze_fields = cloze_fields(first.nodes());
+    if !cloze_fields.is_empty() {
+        return cloze_ords(&cloze_fields, fields);
+    }
+    let nonempty = nonempty_fields(fields);
+    (0u32..)
+        .zip(fronts)
+        .filter(|(_, front)| !template_is_empty(front.nodes(), &nonempty))
+        .map(|(ord, _)| ord)
+        .collect()
+}
+
+/// One card of a batch render
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeckCard {
+    /// Index of the note in the input
+    pub note_index: usize,
+    /// Card ordinal, as for [`CardTemplates::render`]
+    pub ord: u32,
+    #[serde(flatten)]
+    pub card: RenderedCard,
+}
+
+/// Render every card of every note, parsing the note type's templates once.
+///
+/// Standard notes produce one card each; cloze notes produce one card per
+/// cloze number. Cards are returned in note order, then ordinal order.
+pub fn render_deck(
+    note_type: &NoteType,
+    notes: &[HashMap<String, String>],
+) -> Result<Vec<DeckCard>, TemplateError> {
+    let templates = CardTemplates::new(&note_type.front, &note_type.back)?;
+    let mut cards = Vec::new();
+    for (note_index, fields) in notes.iter().enumerate() {
+        for ord in templates.card_ords(fields) {
+            cards.push(DeckCard {
+                note_index,
+                ord,
+                card: templates.render(fields, ord),
+            });
+        }
+    }
+    Ok(cards)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::TemplateErrorKind;
+
+    fn fields(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn note_type(front: &str, back: &str) -> NoteType {
+        NoteType {
+            front: front.to_string(),
+            back: back.to_string(),
+            css: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_render_feeds_front_side() {
+        let templates = CardTemplates::new("{{Front}}", "{{FrontSide}}<hr>{{Back}}").unwrap();
+        let card = templates.render(&fields(&[("Front", "Q"), ("Back", "A")]), 0);
+        assert_eq!(card.question, "Q");
+        assert_eq!(card.answer, "Q<hr>A");
+    }
+
+    #[test]
+    fn test_render_cloze_front_side() {
+        let templates =
+            CardTemplates::new("{{cloze:Text}}", "{{FrontSide}}|{{cloze:Text}}").unwrap();
+        let card = templates.render(&fields(&[("Text", "{{c1::a}} {{c2::b}}")]), 2);
+        let question = "<span class=\"cloze-inactive\" data-ordinal=\"1\">a</span> \
+                        <span class=\"cloze\" data-cloze=\"b\" data-ordinal=\"2\">[...]</span>";
+        assert_eq!(card.question, question);
+        assert_eq!(
+            card.answer,
+            format!(
+                "{}|<span class=\"cloze-inactive\" data-ordinal=\"1\">a</span> \
+                 <span class=\"cloze\" data-ordinal=\"2\">b</span>",
+                question
+            )
+        );
+    }
+
+    #[test]
+    fn test_render_with_typed_answer() {
+        let templates =
+            CardTemplates::new("{{Front}} {{type:Back}}", "{{FrontSide}}<hr id=answer>{{Back}}")
+                .unwrap();
+        let fields = fields(&[("Front", "Capital?"), ("Back", "Paris")]);
+        let card = templates.render_with_typed_answer(&fields, 0, "Paris");
+        assert_eq!(card.question, templates.render(&fields, 0).question);
+        assert!(card.question.contains("<input"));
+        assert_eq!(
+            card.answer,
+            "Capital? <code id=typeans><span class=typeGood>Paris</span></code>\
+             <hr id=answer>Paris"
+        );
+    }
+
+    #[test]
+    fn test_render_cloze_with_typed_answer() {
+        let templates = CardTemplates::new(
+            "{{cloze:Text}}<br>{{type:cloze:Text}}",
+            "{{cloze:Text}}<br>{{type:cloze:Text}}",
+        )
+        .unwrap();
+        let fields = fields(&[("Text", "{{c1::Paris}} is in {{c2::France}}")]);
+        let card = templates.render_with_typed_answer(&fields, 2, "Frence");
+        assert!(card.question.contains("data-expected=\"France\""));
+        assert!(card.answer.ends_with(
+            "<br><code id=typeans><span class=typeGood>Fr</span><span class=typeBad>e</span>\
+             <span class=typeGood>nce</span><br><span id=typearrow>&darr;</span><br>\
+             <span class=typeGood>Fr</span><span class=typeMissed>a</span>\
+             <span class=typeGood>nce</span></code>"
+        ));
+    }
+
+    #[test]
+    fn test_front_side_strips_audio() {
+        let card = render_card(
+            "{{Front}}[sound:q.mp3]",
+            "{{FrontSide}}<hr id=answer>{{Back}}",
+            &fields(&[
+                ("Front", "Hund [anki:tts lang=de_DE]Hund[/anki:tts]"),
+                ("Back", "dog [sound:a.mp3]"),
+            ]),
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            card.question,
+            "Hund [anki:tts lang=de_DE]Hund[/anki:tts][sound:q.mp3]"
+        );
+        assert_eq!(card.answer, "Hund <hr id=answer>dog [sound:a.mp3]");
+    }
+
+    #[test]
+    fn test_answer_only() {
+        let card = render_card(
+            "{{Front}}",
+            "{{FrontSide}}\n\n<hr id=answer>\n\n{{Back}}",
+            &fields(&[("Front", "Q"), ("Back", "A")]),
+            0,
+        )
+        .unwrap();
+        assert_eq!(card.answer_only(), "\n\nA");
+
+        for anchor in ["<hr id=\"answer\">", "<HR class=x id='answer' />"] {
+            let back = format!("{{{{FrontSide}}}}{}{{{{Back}}}}", anchor);
+            let card = render_card(
+                "{{Front}}",
+                &back,
+                &fields(&[("Front", "Q"), ("Back", "A")]),
+                0,
+            )
+            .unwrap();
+            assert_eq!(card.answer_only(), "A", "{}", anchor);
+        }
+
+        let card = render_card(
+            "{{Front}}",
+            "<hr id=answers>{{Back}}",
+            &fields(&[("Back", "A")]),
+            0,
+        )
+        .unwrap();
+        assert_eq!(card.answer_only(), card.answer);
+    }
+
+    #[test]
+    fn test_render_card_error() {
+        let err = render_card("{{#Front}}", "", &HashMap::new(), 0).unwrap_err();
+        assert_eq!(err.kind, TemplateErrorKind::UnclosedBlock);
+    }
+
+    #[test]
+    fn test_question_is_empty() {
+        let templates = CardTemplates::new(
+            "<b>Q:</b> {{Front}}{{#Extra}}{{Extra}}{{/Extra}}",
+            "{{Back}}",
+        )
+        .unwrap();
+        let empty = |pairs: &[(&str, &str)]| templates.render(&fields(pairs), 0).question_is_empty;
+        assert!(empty(&[]));
+        assert!(empty(&[
+            ("Front", " \n<br> <div>&nbsp;</div>"),
+            ("Back", "A")
+        ]));
+        assert!(!empty(&[("Front", "Q")]));
+        assert!(!empty(&[("Extra", "E")]));
+        assert!(!empty(&[("Front", "<br>x")]));
+    }
+
+    #[test]
+    fn test_question_is_empty_ignores_special_fields() {
+        let templates = CardTemplates::new("{{Tags}}{{Deck}}", "").unwrap();
+        let note = fields(&[("Tags", "tag"), ("Deck", "Default")]);
+        assert!(templates.render(&note, 0).question_is_empty);
+    }
+
+    #[test]
+    fn test_question_is_empty_conditionals() {
+        // Text inside a block on a non-empty field is still just text
+        let positive = CardTemplates::new("{{#Extra}}static{{/Extra}}", "").unwrap();
+        assert!(positive.question_is_empty(&fields(&[("Extra", "E")]), 0));
+        // Like Anki, negated blocks are searched whatever their field
+        let negative = CardTemplates::new("{{^Extra}}{{Front}}{{/Extra}}", "").unwrap();
+        assert!(!negative.question_is_empty(&fields(&[("Extra", "E"), ("Front", "F")]), 0));
+        // A block on an empty field hides its contents
+        let hidden = CardTemplates::new("{{#Extra}}{{Front}}{{/Extra}}", "").unwrap();
+        assert!(hidden.question_is_empty(&fields(&[("Extra", "<br>"), ("Front", "F")]), 0));
+    }
+
+    #[test]
+    fn test_cloze_question_is_empty() {
+        let templates = CardTemplates::new("{{cloze:Text}}", "{{cloze:Text}}").unwrap();
+        let note = fields(&[("Text", "{{c1::a}} {{c2::b}}")]);
+        assert!(!templates.render(&note, 2).question_is_empty);
+        assert!(templates.render(&note, 3).question_is_empty);
+        let gaps = fields(&[("Text", "{{c1::a}} {{c3::b}}")]);
+        assert!(templates.render(&gaps, 2).question_is_empty);
+        assert!(
+            templates
+                .render(&fields(&[("Text", "no clozes")]), 1)
+                .question_is_empty
+        );
+    }
+
+    fn compile(templates: &[&str]) -> Vec<CompiledTemplate> {
+        templates
+            .iter()
+            .map(|t| CompiledTemplate::new(t).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_generate_cards_standard() {
+        let fronts = compile(&[
+            "{{Front}}",
+            "{{#Reverse}}{{Back}}{{/Reverse}}",
+            "Always: {{Extra}}",
+        ]);
+        let note = fields(&[("Front", "F"), ("Back", "B")]);
+        assert_eq!(generate_cards(&fronts, &note), vec![0]);
+
+        let note = fields(&[
+            ("Front", "F"),
+            ("Back", "B"),
+            ("Reverse", "y"),
+            ("Extra", "E"),
+        ]);
+        assert_eq!(generate_cards(&fronts, &note), vec![0, 1, 2]);
+
+        let note = fields(&[("Front", "<br>"), ("Back", "B"), ("Reverse", "<div></div>")]);
+        assert_eq!(generate_cards(&fronts, &note), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_generate_cards_cloze() {
+        let fronts = compile(&["{{cloze:Text}}{{#Extra}}{{cloze:Extra}}{{/Extra}}"]);
+        let note = fields(&[
+            ("Text", "{{c1::a}} {{c2::b}}"),
+            ("Extra", "{{c3::c}}"),
+            ("Back Extra", "{{c7::not rendered as cloze}}"),
+        ]);
+        assert_eq!(generate_cards(&fronts, &note), vec![1, 2, 3]);
+
+        let note = fields(&[("Text", "{{c5::a}} {{c1::b}}")]);
+        assert_eq!(generate_cards(&fronts, &note), vec![1, 5]);
+        assert_eq!(
+            generate_cards(&fronts, &fields(&[("Text", "no deletions")])),
+            Vec::<u32>::new()
+        );
+    }
+
+    #[test]
+    fn test_generate_cards_no_templates() {
+        assert_eq!(
+            generate_cards(&[], &fields(&[("Front", "F")])),
+            Vec::<u32>::new()
+        );
+    }
+
+    #[test]
+    fn test_cloze_detection() {
+        assert!(!CardTemplates::new("{{Front}}", "{{Back}}")
+            .unwrap()
+            .is_cloze());
+        assert!(CardTemplates::new("{{#Text}}{{cloze:Text}}{{/Text}}", "")
+            .unwrap()
+            .is_cloze());
+    }
+
+    #[test]
+    fn test_card_ords() {
+        let standard = CardTemplates::new("{{Front}}", "{{Back}}").unwrap();
+        assert_eq!(
+            standard.card_ords(&fields(&[("Front", "{{c2::x}}")])),
+            vec![0]
+        );
+
+        let cloze = CardTemplates::new("{{cloze:Text}}{{cloze:Extra}}", "").unwrap();
+        let note = fields(&[
+            ("Text", "{{c1::a}}"),
+            ("Extra", "{{c3::b}}"),
+            ("Other", "{{c9::c}}"),
+        ]);
+        assert_eq!(cloze.card_ords(&note), vec![1, 3]);
+        assert_eq!(cloze.card_ords(&fields(&[("Text", "none")])), vec![1]);
+    }
+
+    #[test]
+    fn test_render_deck() {
+        let cloze = note_type("{{cloze:Text}}", "{{cloze:Text}}");
+        let notes = [
+            fields(&[("Text", "{{c1::a}} {{c2::b}}")]),
+            fields(&[("Text", "{{c1::c}}")]),
+        ];
+        let cards = render_deck(&cloze, &notes).unwrap();
+        let ids: Vec<_> = cards.iter().map(|c| (c.note_index, c.ord)).collect();
+        assert_eq!(ids, vec![(0, 1), (0, 2), (1, 1)]);
+        assert_eq!(
+            cards[2].card.answer,
+            "<span class=\"cloze\" data-ordinal=\"1\">c</span>"
+        );
+    }
+
+    #[test]
+    fn test_render_deck_json_shape() {
+        let cards = render_deck(
+            &note_type("{{F}}", "{{FrontSide}}!"),
+            &[fields(&[("F", "x")])],
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&cards).unwrap(),
+            serde_json::json!([{
+                "noteIndex": 0,
+                "ord": 0,
+                "question": "x",
+                "answer": "x!",
+                "questionIsEmpty": false
+            }])
+        );
+    }
+
+    #[test]
+    fn test_render_deck_template_error() {
+        let err = render_deck(&note_type("{{Front}}", "{{/Back}}"), &[]).unwrap_err();
+        assert_eq!(err.kind, TemplateErrorKind::UnopenedClose);
+    }
+}
@@ -1,12 +1,256 @@
-use regex::Regex;
-use std::sync::LazyLock;
+use std::collections::{BTreeSet, HashSet};
 
-/// Regex for matching cloze deletions: {{c1::text}} or {{c1::text::hint}}
-static CLOZE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(r"\{\{c(\d+)::([^}]*?)(::([^}]*?))?\}\}").unwrap()
-});
+/// A piece of field content, as split up by [`tokenize`]
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    /// Start of a deletion, e.g. `{{c1::` or `{{c1,2::`
+    OpenCloze {
+        ordinals: Vec<u32>,
+        marker: &'a str,
+    },
+    Text(&'a str),
+    /// `}}`, closing the innermost open deletion
+    CloseCloze,
+}
+
+/// Deepest nesting of deletions. Deletions inside this many open ones are
+/// kept as text, close marker included, so that rendering, which recurses once per level,
+/// cannot exhaust the stack on hostile field content.
+const MAX_CLOZE_DEPTH: usize = 64;
+
+/// Parse a deletion's opening marker at the start of `text`, returning the
+/// marker's length and the card numbers it lists. A marker listing no card
+/// number above 0, like `{{c0::`, is not a deletion.
+fn open_cloze(text: &str) -> Option<(usize, Vec<u32>)> {
+    let rest = text.strip_prefix("{{c")?;
+    let list_len = rest
+        .find(|c: char| !c.is_ascii_digit() && c != ',')
+        .unwrap_or(rest.len());
+    let list = &rest[..list_len];
+    let valid = list.split(',').all(|n| !n.is_empty());
+    if !valid || !rest[list_len..].starts_with("::") {
+        return None;
+    }
+    let ordinals: Vec<u32> = list
+        .split(',')
+        .filter_map(|n| n.parse::<u32>().ok())
+        .filter(|&n| n > 0)
+        .collect();
+    if ordinals.is_empty() {
+        return None;
+    }
+    Some(("{{c".len() + list_len + "::".len(), ordinals))
+}
+
+/// Split field content into cloze markers and text.
+///
+/// Braces inside a deletion are balanced before its close marker is
+/// looked for: each `{` (including those of a `{{` that does not open a
+/// deletion) must be matched by a `}` first. MathJax such as
+/// `{{c1::\(x^{2}\)}}` or code with braces therefore stays inside the
+/// deletion instead of closing it early. A deletion whose braces cannot be
+/// balanced before the end of the field, as in `{{c1::a { b}}`, is closed
+/// by the first `}}` after its marker instead, like in Anki.
+///
+/// The field is scanned at most twice: once balancing every deletion, and
+/// again without balancing those found to take the close markers of
+/// deletions left open.
+fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let (tokens, unbalanced) = tokenize_balancing(text, &HashSet::new());
+    if unbalanced.is_empty() {
+        return tokens;
+    }
+    tokenize_balancing(text, &unbalanced).0
+}
+
+/// Braces seen in the content of an open deletion
+struct BraceDepth {
+    /// Byte position of the deletion's marker
+    marker_pos: usize,
+    /// Unmatched `{` so far
+    depth: usize,
+    /// Whether braces are counted at all
+    balanced: bool,
+    /// Whether a `}}` has been taken as braces while this deletion was the
+    /// innermost open one
+    skipped_close: bool,
+    /// Whether the deletion is nested too deeply and kept as text
+    as_text: bool,
+}
+
+/// Tokenize `text`, balancing braces in every deletion except those whose
+/// marker is at a position in `unbalanced`.
+///
+/// Also returns the marker positions of deletions that should not be
+/// balanced, if any are left open at the end: the close markers those
+/// needed were taken as braces by deletions from the outermost open one
+/// on. As many such deletions as were left open are returned, the last
+/// ones in the field, since a `}}` skipped late can only have been needed
+/// by a deletion open at that point.
+fn tokenize_balancing<'a>(
+    text: &'a str,
+    unbalanced: &HashSet<usize>,
+) -> (Vec<Token<'a>>, HashSet<usize>) {
+    let mut tokens = Vec::new();
+    // Each open deletion, innermost last
+    let mut open: Vec<BraceDepth> = Vec::new();
+    // Marker positions of deletions that skipped a `}}`
+    let mut skipped = Vec::new();
+    let mut text_start = 0;
+    let mut pos = 0;
+
+    let flush_text = |tokens: &mut Vec<Token<'a>>, start: usize, end: usize| {
+        if start < end {
+            tokens.push(Token::Text(&text[start..end]));
+        }
+    };
+
+    // Markers are ASCII, so byte positions at braces are char boundaries
+    while let Some(offset) = text[pos..].find(['{', '}']) {
+        pos += offset;
+        if text.as_bytes()[pos] == b'{' {
+            if let Some((len, ordinals)) = open_cloze(&text[pos..]) {
+                let as_text = open.len() >= MAX_CLOZE_DEPTH;
+                if !as_text {
+                    flush_text(&mut tokens, text_start, pos);
+                    tokens.push(Token::OpenCloze {
+                        ordinals,
+                        marker: &text[pos..pos + len],
+                    });
+                    text_start = pos + len;
+                }
+                open.push(BraceDepth {
+                    marker_pos: pos,
+                    depth: 0,
+                    balanced: !unbalanced.contains(&pos),
+                    skipped_close: false,
+                    as_text,
+                });
+                pos += len;
+                continue;
+            }
+            if let Some(cloze) = open.last_mut().filter(|cloze| cloze.balanced) {
+                cloze.depth += 1;
+            }
+        } else if let Some(cloze) = open.last_mut().filter(|cloze| cloze.depth > 0) {
+            if !cloze.skipped_close && text[pos..].starts_with("}}") {
+                cloze.skipped_close = true;
+                skipped.push(cloze.marker_pos);
+            }
+            cloze.depth -= 1;
+        } else if text[pos..].starts_with("}}") {
+            if open.pop().is_some_and(|cloze| !cloze.as_text) {
+                flush_text(&mut tokens, text_start, pos);
+                tokens.push(Token::CloseCloze);
+                text_start = pos + 2;
+            }
+            pos += 2;
+            continue;
+        }
+        pos += 1;
+    }
+    flush_text(&mut tokens, text_start, text.len());
+
+    let mut culprits = HashSet::new();
+    if let Some(outermost) = open.first() {
+        skipped.retain(|&marker_pos| marker_pos >= outermost.marker_pos);
+        skipped.sort_unstable();
+        let first = skipped.len().saturating_sub(open.len());
+        culprits.extend(&skipped[first..]);
+    }
+    (tokens, culprits)
+}
+
+/// Field content as a tree of text and (possibly nested) deletions
+#[derive(Debug, Clone, PartialEq)]
+enum TextOrCloze<'a> {
+    Text(&'a str),
+    Cloze(Cloze<'a>),
+}
+
+/// A cloze deletion
+#[derive(Debug, Clone, PartialEq)]
+struct Cloze<'a> {
+    /// Cards on which the deletion is hidden
+    ordinals: Vec<u32>,
+    nodes: Vec<TextOrCloze<'a>>,
+    hint: Option<&'a str>,
+}
 
-/// Render cloze deletions in field content.
+/// A deletion whose close marker has not been reached yet
+struct OpenCloze<'a> {
+    marker: &'a str,
+    cloze: Cloze<'a>,
+}
+
+/// Parse field content into a tree of text and deletions.
+///
+/// A `}}` closes the innermost open deletion, and a hint is whatever
+/// follows `::` in the deletion's last piece of text. Stray `}}` and
+/// deletions that are never closed are kept as plain text.
+fn parse_clozes(text: &str) -> Vec<TextOrCloze<'_>> {
+    let mut output = Vec::new();
+    let mut open: Vec<OpenCloze> = Vec::new();
+
+    fn push<'a>(
+        open: &mut [OpenCloze<'a>],
+        output: &mut Vec<TextOrCloze<'a>>,
+        node: TextOrCloze<'a>,
+    ) {
+        match open.last_mut() {
+            Some(parent) => parent.cloze.nodes.push(node),
+            None => output.push(node),
+        }
+    }
+
+    for token in tokenize(text) {
+        match token {
+            Token::OpenCloze { ordinals, marker } => open.push(OpenCloze {
+                marker,
+                cloze: Cloze {
+                    ordinals,
+                    nodes: Vec::new(),
+                    hint: None,
+                },
+            }),
+            Token::Text(text) => push(&mut open, &mut output, TextOrCloze::Text(text)),
+            Token::CloseCloze => match open.pop() {
+                Some(OpenCloze { mut cloze, .. }) => {
+                    if let Some(TextOrCloze::Text(text)) = cloze.nodes.last_mut() {
+                        if let Some((before, hint)) = text.split_once("::") {
+                            *text = before;
+                            cloze.hint = Some(hint);
+                        }
+                    }
+                    push(&mut open, &mut output, TextOrCloze::Cloze(cloze));
+                }
+                None => push(&mut open, &mut output, TextOrCloze::Text("}}")),
+            },
+        }
+    }
+
+    // Deletions still open at the end are not deletions after all. Each
+    // holds what follows its marker up to the next one, so outermost first
+    // their markers and content make up the rest of the field.
+    for unclosed in open {
+        output.push(TextOrCloze::Text(unclosed.marker));
+        output.extend(unclosed.cloze.nodes);
+    }
+
+    output
+}
+
+/// Render cloze deletions in field content, with Anki's markup.
+///
+/// Active deletions become `<span class="cloze">`, carrying the card
+/// numbers in `data-ordinal` and, on the question side, the hidden text in
+/// `data-cloze`. Inactive deletions are wrapped in
+/// `<span class="cloze-inactive">` with `data-ordinal`.
+///
+/// Deletions may be nested: on the question side an active deletion hides
+/// everything inside it, including nested deletions, while an inactive one
+/// shows its content with any nested active deletion hidden. On the answer
+/// side active deletions are highlighted at every level.
 ///
 /// # Arguments
 /// * `field_content` - The field content containing cloze markers
@@ -16,47 +260,166 @@ static CLOZE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
 /// # Returns
 /// The rendered content with cloze markers processed
 pub fn render_cloze(field_content: &str, card_ord: u32, is_question: bool) -> String {
-    CLOZE_REGEX
-        .replace_all(field_content, |caps: &regex::Captures| {
-            let cloze_num: u32 = caps[1].parse().unwrap_or(0);
-            let text = &caps[2];
-            let hint = caps.get(4).map(|m| m.as_str());
-
-            if cloze_num == card_ord {
-                // This is the active cloze for this card
-                if is_question {
-                    // Question side: hide the content
-                    match hint {
-                        Some(h) => format!("<span class=\"cloze\">[{}]</span>", h),
-                        None => "<span class=\"cloze\">[...]</span>".to_string(),
-                    }
+    let mut output = String::new();
+    render_nodes(
+        &parse_clozes(field_content),
+        card_ord,
+        is_question,
+        &mut output,
+    );
+    output
+}
+
+fn render_nodes(nodes: &[TextOrCloze], card_ord: u32, is_question: bool, output: &mut String) {
+    for node in nodes {
+        match node {
+            TextOrCloze::Text(text) => output.push_str(text),
+            TextOrCloze::Cloze(cloze) => {
+                let ordinals = ordinals_attribute(&cloze.ordinals);
+                if !cloze.ordinals.contains(&card_ord) {
+                    // Inactive cloze: show the content
+                    output.push_str(&format!(
+                        "<span class=\"cloze-inactive\" data-ordinal=\"{}\">",
+                        ordinals
+                    ));
+                    render_nodes(&cloze.nodes, card_ord, is_question, output);
+                    output.push_str("</span>");
+                } else if is_question {
+                    // Question side: hide the content, keeping the answer in
+                    // data-cloze for click-to-reveal scripts
+                    let mut answer = String::new();
+                    render_nodes(&cloze.nodes, card_ord, false, &mut answer);
+                    output.push_str(&format!(
+                        "<span class=\"cloze\" data-cloze=\"{}\" data-ordinal=\"{}\">[{}]</span>",
+                        htmlescape::encode_attribute(&answer),
+                        ordinals,
+                        cloze.hint.unwrap_or("...")
+                    ));
                 } else {
                     // Answer side: reveal with styling
-                    format!("<span class=\"cloze\">{}</span>", text)
+                    output.push_str(&format!(
+                        "<span class=\"cloze\" data-ordinal=\"{}\">",
+                        ordinals
+                    ));
+                    render_nodes(&cloze.nodes, card_ord, is_question, output);
+                    output.push_str("</span>");
                 }
-            } else {
-                // Inactive cloze: just show the text
-                text.to_string()
             }
+        }
+    }
+}
+
+/// Render only the active deletions in field content, for the `cloze-only`
+/// filter: each one's hint (or `...`) on the question side and its text on
+/// the answer side, joined with `, `. Text outside them is dropped.
+pub fn reveal_cloze_text_only(field_content: &str, card_ord: u32, is_question: bool) -> String {
+    let mut output = Vec::new();
+    collect_active_text(
+        &parse_clozes(field_content),
+        card_ord,
+        is_question,
+        &mut output,
+    );
+    output.join(", ")
+}
+
+fn collect_active_text(
+    nodes: &[TextOrCloze],
+    card_ord: u32,
+    is_question: bool,
+    output: &mut Vec<String>,
+) {
+    for node in nodes {
+        if let TextOrCloze::Cloze(cloze) = node {
+            if cloze.ordinals.contains(&card_ord) {
+                output.push(if is_question {
+                    cloze.hint.unwrap_or("...").to_string()
+                } else {
+                    clozed_text(&cloze.nodes)
+                });
+            }
+            collect_active_text(&cloze.nodes, card_ord, is_question, output);
+        }
+    }
+}
+
+/// The answer expected by `{{type:cloze:Field}}`: the text of the active
+/// deletions, joined with `, `. As in Anki, text deleted several times is
+/// only expected once when every active deletion has the same text.
+pub fn typed_cloze_answer(field_content: &str, card_ord: u32) -> String {
+    let mut answers = Vec::new();
+    collect_active_text(&parse_clozes(field_content), card_ord, false, &mut answers);
+    if answers.windows(2).all(|pair| pair[0] == pair[1]) {
+        answers.truncate(1);
+    }
+    answers.join(", ")
+}
+
+/// The text of a deletion's content, with nested deletions revealed and
+/// no markup
+fn clozed_text(nodes: &[TextOrCloze]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node {
+            TextOrCloze::Text(text) => text.to_string(),
+            TextOrCloze::Cloze(cloze) => clozed_text(&cloze.nodes),
         })
-        .to_string()
+        .collect()
+}
+
+/// The `data-ordinal` value for a deletion's card numbers, e.g. `1,2`
+fn ordinals_attribute(ordinals: &[u32]) -> String {
+    ordinals
+        .iter()
+        .map(|ord| ord.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Add the card numbers of every deletion, nested or not, to `ords`
+fn collect_ordinals(nodes: &[TextOrCloze], ords: &mut BTreeSet<u32>) {
+    for node in nodes {
+        if let TextOrCloze::Cloze(cloze) = node {
+            ords.extend(&cloze.ordinals);
+            collect_ordinals(&cloze.nodes, ords);
+        }
+    }
+}
+
+/// Add the cloze numbers in field content to `ords`
+pub fn add_cloze_ordinals(field_content: &str, ords: &mut BTreeSet<u32>) {
+    collect_ordinals(&parse_clozes(field_content), ords);
+}
+
+/// The cloze numbers used across one or more fields, sorted and without
+/// duplicates. Each is the ordinal of a card the note generates.
+pub fn cloze_ordinals<'a>(fields: impl IntoIterator<Item = &'a str>) -> Vec<u32> {
+    let mut ords = BTreeSet::new();
+    for field_content in fields {
+        add_cloze_ordinals(field_content, &mut ords);
+    }
+    ords.into_iter().collect()
 }
 
 /// Count the number of unique cloze ordinals in field content.
 /// This determines how many cards a cloze note generates.
 pub fn count_cloze_ordinals(field_content: &str) -> u32 {
-    let mut max_ord = 0;
-    for caps in CLOZE_REGEX.captures_iter(field_content) {
-        if let Ok(ord) = caps[1].parse::<u32>() {
-            max_ord = max_ord.max(ord);
-        }
-    }
-    max_ord
+    cloze_ordinals([field_content]).len() as u32
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use regex::Regex;
+
+    /// Drop `data-cloze` attributes, to compare the structure of output
+    /// whose hidden text is long once escaped
+    fn without_data_cloze(html: &str) -> String {
+        Regex::new(r#" data-cloze="[^"]*""#)
+            .unwrap()
+            .replace_all(html, "")
+            .into_owned()
+    }
 
     #[test]
     fn test_cloze_question_basic() {
@@ -64,7 +427,8 @@ mod tests {
         let result = render_cloze(field, 1, true);
         assert_eq!(
             result,
-            "<span class=\"cloze\">[...]</span> is the capital of France"
+            "<span class=\"cloze\" data-cloze=\"Paris\" data-ordinal=\"1\">[...]</span> \
+             is the capital of <span class=\"cloze-inactive\" data-ordinal=\"2\">France</span>"
         );
     }
 
@@ -74,7 +438,8 @@ mod tests {
         let result = render_cloze(field, 2, true);
         assert_eq!(
             result,
-            "Paris is the capital of <span class=\"cloze\">[...]</span>"
+            "<span class=\"cloze-inactive\" data-ordinal=\"1\">Paris</span> is the capital of \
+             <span class=\"cloze\" data-cloze=\"France\" data-ordinal=\"2\">[...]</span>"
         );
     }
 
@@ -84,7 +449,8 @@ mod tests {
         let result = render_cloze(field, 1, false);
         assert_eq!(
             result,
-            "<span class=\"cloze\">Paris</span> is the capital of France"
+            "<span class=\"cloze\" data-ordinal=\"1\">Paris</span> is the capital of \
+             <span class=\"cloze-inactive\" data-ordinal=\"2\">France</span>"
         );
     }
 
@@ -94,7 +460,8 @@ mod tests {
         let result = render_cloze(field, 1, true);
         assert_eq!(
             result,
-            "<span class=\"cloze\">[capital city]</span> is in France"
+            "<span class=\"cloze\" data-cloze=\"Paris\" data-ordinal=\"1\">[capital city]</span> \
+             is in France"
         );
     }
 
@@ -105,7 +472,32 @@ mod tests {
         // Answer side shows text, not hint
         assert_eq!(
             result,
-            "<span class=\"cloze\">Paris</span> is in France"
+            "<span class=\"cloze\" data-ordinal=\"1\">Paris</span> is in France"
+        );
+    }
+
+    #[test]
+    fn test_data_cloze_is_escaped() {
+        let field = "{{c1::say \"hi\" & <b>bye</b>}}";
+        assert_eq!(
+            render_cloze(field, 1, true),
+            "<span class=\"cloze\" data-cloze=\"\
+             say&#x20;&quot;hi&quot;&#x20;&amp;&#x20;&lt;b&gt;bye&lt;&#x2F;b&gt;\" \
+             data-ordinal=\"1\">[...]</span>"
+        );
+    }
+
+    #[test]
+    fn test_data_cloze_holds_revealed_nested_content() {
+        let field = "{{c1::a {{c2::b}}}}";
+        assert_eq!(
+            render_cloze(field, 1, true),
+            format!(
+                "<span class=\"cloze\" data-cloze=\"{}\" data-ordinal=\"1\">[...]</span>",
+                htmlescape::encode_attribute(
+                    "a <span class=\"cloze-inactive\" data-ordinal=\"2\">b</span>"
+                )
+            )
         );
     }
 
@@ -115,6 +507,320 @@ mod tests {
         assert_eq!(count_cloze_ordinals(field), 3);
     }
 
+    #[test]
+    fn test_count_cloze_ordinals_with_gaps() {
+        let field = "{{c1::a}} {{c5::b}}";
+        assert_eq!(count_cloze_ordinals(field), 2);
+    }
+
+    #[test]
+    fn test_cloze_ordinals_sorted_unique() {
+        let field = "{{c5::a}} {{c1::b}} {{c5::c}} {{c12::d}}";
+        assert_eq!(cloze_ordinals([field]), vec![1, 5, 12]);
+    }
+
+    #[test]
+    fn test_cloze_ordinals_across_fields() {
+        let fields = ["{{c3::a}}", "plain", "{{c1::b}} {{c3::c}}"];
+        assert_eq!(cloze_ordinals(fields), vec![1, 3]);
+        assert_eq!(cloze_ordinals(["plain"]), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_cloze_ordinals_ignore_zero() {
+        assert_eq!(cloze_ordinals(["{{c0::a}} {{c2::b}}"]), vec![2]);
+        assert_eq!(cloze_ordinals(["{{c0,1::a}}"]), vec![1]);
+    }
+
+    #[test]
+    fn test_zero_ordinal_marker_kept_as_text() {
+        assert_eq!(open_cloze("{{c0::a}}"), None);
+        assert_eq!(open_cloze("{{c00,0::a}}"), None);
+        assert_eq!(
+            render_cloze("{{c0::a}} {{c2::b}}", 2, false),
+            "{{c0::a}} <span class=\"cloze\" data-ordinal=\"2\">b</span>"
+        );
+    }
+
+    #[test]
+    fn test_multi_ordinal_cloze_active_on_each_card() {
+        let field = "{{c1,2::shared}} and {{c2::own}}";
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 1, true)),
+            "<span class=\"cloze\" data-ordinal=\"1,2\">[...]</span> and \
+             <span class=\"cloze-inactive\" data-ordinal=\"2\">own</span>"
+        );
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 2, true)),
+            "<span class=\"cloze\" data-ordinal=\"1,2\">[...]</span> and \
+             <span class=\"cloze\" data-ordinal=\"2\">[...]</span>"
+        );
+        assert_eq!(
+            render_cloze(field, 3, true),
+            "<span class=\"cloze-inactive\" data-ordinal=\"1,2\">shared</span> and \
+             <span class=\"cloze-inactive\" data-ordinal=\"2\">own</span>"
+        );
+    }
+
+    #[test]
+    fn test_multi_ordinal_cloze_hint_and_answer() {
+        let field = "{{c3,1::Paris::city}}";
+        assert_eq!(
+            render_cloze(field, 3, true),
+            "<span class=\"cloze\" data-cloze=\"Paris\" data-ordinal=\"3,1\">[city]</span>"
+        );
+        assert_eq!(
+            render_cloze(field, 1, false),
+            "<span class=\"cloze\" data-ordinal=\"3,1\">Paris</span>"
+        );
+    }
+
+    #[test]
+    fn test_multi_ordinal_cloze_counts_every_number() {
+        let field = "{{c1,3::a}} {{c2::b}} {{c3,5::c}}";
+        assert_eq!(cloze_ordinals([field]), vec![1, 2, 3, 5]);
+        assert_eq!(count_cloze_ordinals(field), 4);
+    }
+
+    #[test]
+    fn test_nested_cloze_question_sides() {
+        let field = "{{c1::outer {{c2::inner}} text}} rest";
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 1, true)),
+            "<span class=\"cloze\" data-ordinal=\"1\">[...]</span> rest"
+        );
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 2, true)),
+            "<span class=\"cloze-inactive\" data-ordinal=\"1\">outer \
+             <span class=\"cloze\" data-ordinal=\"2\">[...]</span> text</span> rest"
+        );
+    }
+
+    #[test]
+    fn test_nested_cloze_answer_sides() {
+        let field = "{{c1::outer {{c2::inner}} text}} rest";
+        assert_eq!(
+            render_cloze(field, 1, false),
+            "<span class=\"cloze\" data-ordinal=\"1\">outer \
+             <span class=\"cloze-inactive\" data-ordinal=\"2\">inner</span> text</span> rest"
+        );
+        assert_eq!(
+            render_cloze(field, 2, false),
+            "<span class=\"cloze-inactive\" data-ordinal=\"1\">outer \
+             <span class=\"cloze\" data-ordinal=\"2\">inner</span> text</span> rest"
+        );
+    }
+
+    #[test]
+    fn test_nested_cloze_hints() {
+        let field = "{{c1::a {{c2::b::inner hint}}::outer hint}}";
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 1, true)),
+            "<span class=\"cloze\" data-ordinal=\"1\">[outer hint]</span>"
+        );
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 2, true)),
+            "<span class=\"cloze-inactive\" data-ordinal=\"1\">a \
+             <span class=\"cloze\" data-ordinal=\"2\">[inner hint]</span></span>"
+        );
+    }
+
+    #[test]
+    fn test_nested_same_ordinal_both_revealed() {
+        let field = "{{c1::a {{c1::b}} c}}";
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 1, true)),
+            "<span class=\"cloze\" data-ordinal=\"1\">[...]</span>"
+        );
+        assert_eq!(
+            render_cloze(field, 1, false),
+            "<span class=\"cloze\" data-ordinal=\"1\">a \
+             <span class=\"cloze\" data-ordinal=\"1\">b</span> c</span>"
+        );
+    }
+
+    #[test]
+    fn test_nested_cloze_ordinals() {
+        let field = "{{c1::a {{c3::b {{c2::c}}}}}}";
+        assert_eq!(cloze_ordinals([field]), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_unbalanced_markers_kept_as_text() {
+        assert_eq!(render_cloze("a }} b", 1, true), "a }} b");
+        assert_eq!(
+            render_cloze("{{c1::never closed", 1, true),
+            "{{c1::never closed"
+        );
+        assert_eq!(
+            without_data_cloze(&render_cloze("{{c1::open {{c2::closed}}", 2, true)),
+            "{{c1::open <span class=\"cloze\" data-ordinal=\"2\">[...]</span>"
+        );
+        assert_eq!(cloze_ordinals(["{{c1::never closed"]), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_nesting_depth_is_capped() {
+        let levels = 50_000;
+        let field = "{{c1::".repeat(levels) + "x" + &"}}".repeat(levels);
+        let answer = render_cloze(&field, 1, false);
+        assert_eq!(answer.matches("<span").count(), MAX_CLOZE_DEPTH);
+        assert!(answer.ends_with(&"</span>".repeat(MAX_CLOZE_DEPTH)));
+        assert_eq!(render_cloze(&field, 1, true).matches("<span").count(), 1);
+        assert_eq!(
+            reveal_cloze_text_only(&field, 1, false)
+                .matches(", ")
+                .count(),
+            MAX_CLOZE_DEPTH - 1
+        );
+        assert_eq!(cloze_ordinals([field.as_str()]), vec![1]);
+        // Too deep deletions keep their close markers too
+        let field = "{{c1::".repeat(MAX_CLOZE_DEPTH)
+            + "{{c2::y}} {{c3::z}}"
+            + &"}}".repeat(MAX_CLOZE_DEPTH);
+        assert_eq!(cloze_ordinals([field.as_str()]), vec![1]);
+    }
+
+    #[test]
+    fn test_nested_unclosed_markers_kept_in_order() {
+        assert_eq!(
+            render_cloze("{{c1::a {{c2::b {{c3::c}} d {{c4::e", 3, false),
+            "{{c1::a {{c2::b <span class=\"cloze\" data-ordinal=\"3\">c</span> d {{c4::e"
+        );
+        let field = "{{c1::x ".repeat(20_000);
+        assert_eq!(render_cloze(&field, 1, false), field);
+    }
+
+    #[test]
+    fn test_not_a_cloze_marker() {
+        for text in [
+            "{{c::x}}",
+            "{{c1:x}}",
+            "{{c1,::x}}",
+            "{{Front}}",
+            "{{ c1::x}}",
+        ] {
+            assert!(open_cloze(text).is_none(), "{}", text);
+        }
+        assert_eq!(open_cloze("{{c12,3::x"), Some((9, vec![12, 3])));
+    }
+
+    #[test]
+    fn test_cloze_over_mathjax() {
+        let field = "{{c1::\\(\\frac{1}{x^{2}}\\)}} is the derivative";
+        assert_eq!(
+            render_cloze(field, 1, false),
+            "<span class=\"cloze\" data-ordinal=\"1\">\\(\\frac{1}{x^{2}}\\)</span> \
+             is the derivative"
+        );
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 1, true)),
+            "<span class=\"cloze\" data-ordinal=\"1\">[...]</span> is the derivative"
+        );
+    }
+
+    #[test]
+    fn test_cloze_over_code_with_braces() {
+        let field = "{{c1::fn f() { if x { y } }}}";
+        assert_eq!(
+            render_cloze(field, 1, false),
+            "<span class=\"cloze\" data-ordinal=\"1\">fn f() { if x { y } }</span>"
+        );
+    }
+
+    #[test]
+    fn test_cloze_containing_double_braces() {
+        let field = "{{c1::use {{Front}} here::template}}";
+        assert_eq!(
+            render_cloze(field, 1, false),
+            "<span class=\"cloze\" data-ordinal=\"1\">use {{Front}} here</span>"
+        );
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 1, true)),
+            "<span class=\"cloze\" data-ordinal=\"1\">[template]</span>"
+        );
+    }
+
+    #[test]
+    fn test_braces_with_hint_and_nesting() {
+        let field = "{{c1::\\(\\{a\\}\\) {{c2::{b}}}::set}}";
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 1, true)),
+            "<span class=\"cloze\" data-ordinal=\"1\">[set]</span>"
+        );
+        assert_eq!(
+            render_cloze(field, 2, false),
+            "<span class=\"cloze-inactive\" data-ordinal=\"1\">\\(\\{a\\}\\) \
+             <span class=\"cloze\" data-ordinal=\"2\">{b}</span></span>"
+        );
+        assert_eq!(cloze_ordinals([field]), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_unmatched_brace_closes_at_first_marker() {
+        let field = "{{c1::the { key}} rest";
+        assert_eq!(
+            render_cloze(field, 1, false),
+            "<span class=\"cloze\" data-ordinal=\"1\">the { key</span> rest"
+        );
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 1, true)),
+            "<span class=\"cloze\" data-ordinal=\"1\">[...]</span> rest"
+        );
+        assert_eq!(cloze_ordinals([field]), vec![1]);
+        assert_eq!(cloze_ordinals(["{{c1::the { key}} {{c2::x}}"]), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_unmatched_brace_in_nested_cloze() {
+        let field = "{{c1::a {{c2::b { c}} d}} e";
+        assert_eq!(
+            render_cloze(field, 2, false),
+            "<span class=\"cloze-inactive\" data-ordinal=\"1\">a \
+             <span class=\"cloze\" data-ordinal=\"2\">b { c</span> d</span> e"
+        );
+        assert_eq!(
+            render_cloze("{{c1::\\(x^{2}\\)}} {{c2::{ y}}", 1, false),
+            "<span class=\"cloze\" data-ordinal=\"1\">\\(x^{2}\\)</span> \
+             <span class=\"cloze-inactive\" data-ordinal=\"2\">{ y</span>"
+        );
+    }
+
+    #[test]
+    fn test_unmatched_brace_keeps_balanced_neighbours() {
+        let field = "{{c1::a {{c2::\\frac{x}{y}}} {{c3::b { c}} d}} e";
+        assert_eq!(
+            render_cloze(field, 2, false),
+            "<span class=\"cloze-inactive\" data-ordinal=\"1\">a \
+             <span class=\"cloze\" data-ordinal=\"2\">\\frac{x}{y}</span> \
+             <span class=\"cloze-inactive\" data-ordinal=\"3\">b { c</span> d</span> e"
+        );
+    }
+
+    #[test]
+    fn test_many_unmatched_braces_scale_linearly() {
+        let field = "{{c1::{ a}} ".repeat(20_000);
+        let answer = render_cloze(&field, 1, false);
+        assert_eq!(
+            answer
+                .matches("<span class=\"cloze\" data-ordinal=\"1\">{ a</span>")
+                .count(),
+            20_000
+        );
+        let field = "{{c1::\\(x^{2}\\)}} and {{c2::{ y}} ".repeat(5_000);
+        assert_eq!(cloze_ordinals([field.as_str()]), vec![1, 2]);
+        assert_eq!(typed_cloze_answer(&field, 2), "{ y");
+    }
+
+    #[test]
+    fn test_braces_outside_clozes_are_text() {
+        let field = "{ {a} } {{c1::b}} }";
+        assert_eq!(
+            without_data_cloze(&render_cloze(field, 1, true)),
+            "{ {a} } <span class=\"cloze\" data-ordinal=\"1\">[...]</span> }"
+        );
+    }
+
     #[test]
     fn test_no_cloze() {
         let field = "Just plain text";
@@ -122,13 +828,54 @@ mod tests {
         assert_eq!(result, "Just plain text");
     }
 
+    #[test]
+    fn test_cloze_only_answer() {
+        let field = "{{c1::Paris}} is the capital of {{c2::France}}, not {{c1::Lyon}}";
+        assert_eq!(reveal_cloze_text_only(field, 1, false), "Paris, Lyon");
+        assert_eq!(reveal_cloze_text_only(field, 2, false), "France");
+        assert_eq!(reveal_cloze_text_only(field, 3, false), "");
+    }
+
+    #[test]
+    fn test_cloze_only_question_uses_hints() {
+        let field = "{{c1::Paris::city}} and {{c1::France}}";
+        assert_eq!(reveal_cloze_text_only(field, 1, true), "city, ...");
+    }
+
+    #[test]
+    fn test_cloze_only_nested() {
+        let field = "{{c1::a {{c2::b}} {{c1::c}}}}";
+        assert_eq!(reveal_cloze_text_only(field, 1, false), "a b c, c");
+        assert_eq!(reveal_cloze_text_only(field, 2, false), "b");
+        assert_eq!(reveal_cloze_text_only(field, 2, true), "...");
+    }
+
+    #[test]
+    fn test_typed_cloze_answer() {
+        let field = "{{c1::Paris::city}} is the capital of {{c2::France}}, not {{c1::Lyon}}";
+        assert_eq!(typed_cloze_answer(field, 1), "Paris, Lyon");
+        assert_eq!(typed_cloze_answer(field, 2), "France");
+        assert_eq!(typed_cloze_answer(field, 3), "");
+    }
+
+    #[test]
+    fn test_typed_cloze_answer_repeated_text_once() {
+        let field = "{{c1::ni}} hao, {{c1::ni}} hao";
+        assert_eq!(typed_cloze_answer(field, 1), "ni");
+        assert_eq!(
+            typed_cloze_answer("{{c1::a}} {{c1::b}} {{c1::a}}", 1),
+            "a, b, a"
+        );
+    }
+
     #[test]
     fn test_multiple_same_cloze() {
         let field = "{{c1::word1}} and {{c1::word2}}";
         let result = render_cloze(field, 1, true);
         assert_eq!(
             result,
-            "<span class=\"cloze\">[...]</span> and <span class=\"cloze\">[...]</span>"
+            "<span class=\"cloze\" data-cloze=\"word1\" data-ordinal=\"1\">[...]</span> and \
+             <span class=\"cloze\" data-cloze=\"word2\" data-ordinal=\"1\">[...]</span>"
         );
     }
 }
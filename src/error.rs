@@ -0,0 +1,241 @@
+use crate::template::SourcePos;
+use serde::Serialize;
+use std::fmt;
+
+/// What went wrong while parsing a template
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TemplateErrorKind {
+    /// Malformed tag or unexpected content
+    Syntax,
+    /// A close tag that names a different field than the innermost open block
+    MismatchedClose,
+    /// A close tag with no open block at all
+    UnopenedClose,
+    /// An open block that reaches the end of the template without a close tag
+    UnclosedBlock,
+}
+
+impl TemplateErrorKind {
+    /// Stable identifier for the kind, as exposed to JavaScript
+    pub fn name(&self) -> &'static str {
+        match self {
+            TemplateErrorKind::Syntax => "syntax",
+            TemplateErrorKind::MismatchedClose => "mismatchedClose",
+            TemplateErrorKind::UnopenedClose => "unopenedClose",
+            TemplateErrorKind::UnclosedBlock => "unclosedBlock",
+        }
+    }
+}
+
+/// An error in a template, located at the tag that caused it.
+///
+/// For a mismatched or stray close tag the location is the close tag; for a
+/// block that is never closed it is the open tag.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateError {
+    pub kind: TemplateErrorKind,
+    /// Human-readable description, without location prefix
+    pub message: String,
+    /// Byte offset from the start of the template
+    pub offset: usize,
+    /// Line number (1-indexed)
+    pub line: u32,
+    /// Column in characters (1-indexed)
+    pub column: usize,
+    /// The offending source text, e.g. the tag at the error location
+    pub snippet: String,
+    /// Position of the open tag involved, for mismatched and unclosed blocks
+    pub open_pos: Option<SourcePos>,
+}
+
+/// Maximum length in characters of an error snippet
+const MAX_SNIPPET_CHARS: usize = 40;
+
+impl TemplateError {
+    fn new(kind: TemplateErrorKind, message: String, pos: SourcePos, source: &str) -> Self {
+        TemplateError {
+            kind,
+            message,
+            offset: pos.offset,
+            line: pos.line,
+            column: pos.column,
+            snippet: snippet_at(source, pos.offset),
+            open_pos: None,
+        }
+    }
+
+    /// Malformed tag or unexpected content at `pos`
+    pub fn syntax(description: &str, pos: SourcePos, source: &str) -> Self {
+        Self::new(TemplateErrorKind::Syntax, description.to_string(), pos, source)
+    }
+
+    /// Close tag `{{/close_field}}` at `close_pos` while `open_field` is the
+    /// innermost open block
+    pub fn mismatched_close(
+        open_field: &str,
+        is_negative: bool,
+        open_pos: SourcePos,
+        close_field: &str,
+        close_pos: SourcePos,
+        source: &str,
+    ) -> Self {
+        let message = format!(
+            "close tag {{{{/{}}}}} does not match open tag {} opened at line {}, column {}",
+            close_field,
+            open_tag(open_field, is_negative),
+            open_pos.line,
+            open_pos.column
+        );
+        TemplateError {
+            open_pos: Some(open_pos),
+            ..Self::new(TemplateErrorKind::MismatchedClose, message, close_pos, source)
+        }
+    }
+
+    /// Close tag `{{/field}}` at `close_pos` with no open block
+    pub fn unopened_close(field: &str, close_pos: SourcePos, source: &str) -> Self {
+        let message = format!("close tag {{{{/{}}}}} has no matching open tag", field);
+        Self::new(TemplateErrorKind::UnopenedClose, message, close_pos, source)
+    }
+
+    /// Block opened at `open_pos` still open when the template ends at `end_pos`
+    pub fn unclosed_block(
+        field: &str,
+        is_negative: bool,
+        open_pos: SourcePos,
+        end_pos: SourcePos,
+        source: &str,
+    ) -> Self {
+        let message = format!(
+            "block {} is never closed (template ends at line {}, column {})",
+            open_tag(field, is_negative),
+            end_pos.line,
+            end_pos.column
+        );
+        TemplateError {
+            open_pos: Some(open_pos),
+            ..Self::new(TemplateErrorKind::UnclosedBlock, message, open_pos, source)
+        }
+    }
+
+    /// The position the error is reported at
+    pub fn pos(&self) -> SourcePos {
+        SourcePos {
+            offset: self.offset,
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+/// Render an open tag as it appears in the template
+fn open_tag(field: &str, is_negative: bool) -> String {
+    format!("{{{{{}{}}}}}", if is_negative { '^' } else { '#' }, field)
+}
+
+/// Extract the source text at `offset`: the whole tag if one starts there,
+/// otherwise the rest of the line, truncated to a readable length.
+fn snippet_at(source: &str, offset: usize) -> String {
+    let rest = source.get(offset..).unwrap_or("");
+    let line = rest.split('\n').next().unwrap_or("");
+    let tag_end = [("{{", "}}"), ("<%", "%>")]
+        .iter()
+        .filter(|(open, _)| line.starts_with(open))
+        .find_map(|(_, close)| line.find(close).map(|end| end + close.len()));
+    let snippet = &line[..tag_end.unwrap_or(line.len())];
+    snippet.chars().take(MAX_SNIPPET_CHARS).collect()
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Parse error at line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// Why a field could not be renamed
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenameFieldError {
+    /// The new name cannot be written in a tag; holds that name
+    InvalidFieldName(String),
+    /// The template itself does not parse
+    Template(TemplateError),
+}
+
+impl From<TemplateError> for RenameFieldError {
+    fn from(err: TemplateError) -> Self {
+        RenameFieldError::Template(err)
+    }
+}
+
+impl fmt::Display for RenameFieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenameFieldError::InvalidFieldName(name) => write!(f, "Invalid field name: {:?}", name),
+            RenameFieldError::Template(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RenameFieldError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(offset: usize) -> SourcePos {
+        SourcePos {
+            offset,
+            line: 1,
+            column: offset + 1,
+        }
+    }
+
+    #[test]
+    fn test_snippet_is_whole_tag() {
+        let err = TemplateError::unopened_close("Back", pos(4), "abc {{/Back}} def");
+        assert_eq!(err.snippet, "{{/Back}}");
+    }
+
+    #[test]
+    fn test_snippet_is_whole_legacy_tag() {
+        let err = TemplateError::syntax("invalid tag", pos(0), "<%Bad\"%> more");
+        assert_eq!(err.snippet, "<%Bad\"%>");
+    }
+
+    #[test]
+    fn test_snippet_stops_at_line_end() {
+        let err = TemplateError::syntax("expected field name", pos(6), "Hello {{Name\nmore");
+        assert_eq!(err.snippet, "{{Name");
+    }
+
+    #[test]
+    fn test_snippet_truncated() {
+        let source = "x".repeat(100);
+        let err = TemplateError::syntax("unexpected content", pos(0), &source);
+        assert_eq!(err.snippet.len(), MAX_SNIPPET_CHARS);
+    }
+
+    #[test]
+    fn test_display_includes_location() {
+        let err = TemplateError::syntax("expected field name", pos(2), "a {{");
+        assert_eq!(
+            err.to_string(),
+            "Parse error at line 1, column 3: expected field name"
+        );
+        assert_eq!(err.kind.name(), "syntax");
+    }
+
+    #[test]
+    fn test_invalid_field_name_display_has_no_location() {
+        let err = RenameFieldError::InvalidFieldName("a:b".to_string());
+        assert_eq!(err.to_string(), "Invalid field name: \"a:b\"");
+    }
+}
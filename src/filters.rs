@@ -32,8 +32,8 @@ pub fn apply_filter(filter_name: &str, content: &str) -> String {
         "furigana" => filter_furigana(content),
         "kanji" => filter_kanji(content),
         "kana" => filter_kana(content),
-        // cloze is handled separately in template.rs
-        "cloze" => content.to_string(),
+        // cloze and cloze-only are handled separately in template.rs
+        "cloze" | "cloze-only" => content.to_string(),
         // Unknown filters pass through gracefully
         _ => content.to_string(),
     }
@@ -1,9 +1,51 @@
 use std::collections::HashMap;
 use wasm_bindgen::prelude::*;
 
+mod ast;
+mod card;
 mod cloze;
+mod error;
 mod filters;
 mod template;
+mod typeans;
+
+pub use card::{
+    generate_cards, render_card, render_deck, CardTemplates, DeckCard, NoteType, RenderedCard,
+};
+pub use error::{RenameFieldError, TemplateError, TemplateErrorKind};
+pub use template::{
+    field_is_empty, parse_template, parse_template_recovering, print_template, rename_field, CompiledTemplate,
+    SourcePos, SourceSpan, TagSpan, TemplateNode,
+};
+
+/// Convert a template error into a JS `Error` that also carries the error
+/// `kind`, `offset`, `line`, `column` and `snippet` as properties, plus
+/// `openLine`/`openColumn` when an open tag is involved.
+fn template_error_to_js(err: &TemplateError) -> JsValue {
+    let js_err = js_sys::Error::new(&err.to_string());
+    let mut props: Vec<(&str, JsValue)> = vec![
+        ("kind", err.kind.name().into()),
+        ("offset", (err.offset as u32).into()),
+        ("line", err.line.into()),
+        ("column", (err.column as u32).into()),
+        ("snippet", err.snippet.as_str().into()),
+    ];
+    if let Some(open) = err.open_pos {
+        props.push(("openLine", open.line.into()));
+        props.push(("openColumn", (open.column as u32).into()));
+    }
+    for (key, value) in props {
+        // Setting a property on a fresh Error object cannot fail
+        let _ = js_sys::Reflect::set(&js_err, &key.into(), &value);
+    }
+    js_err.into()
+}
+
+/// Parse a JSON object mapping field names to values
+fn parse_fields(fields_json: &str) -> Result<HashMap<String, String>, JsValue> {
+    serde_json::from_str(fields_json)
+        .map_err(|e| JsError::new(&format!("Invalid JSON: {}", e)).into())
+}
 
 /// Render an Anki card template with the given fields.
 ///
@@ -13,12 +55,14 @@ mod template;
 ///
 /// # Returns
 /// The rendered HTML string
+///
+/// On a template error, the thrown `Error` has `kind`, `offset`, `line`,
+/// `column` and `snippet` properties locating the problem.
 #[wasm_bindgen]
-pub fn render_template(template_str: &str, fields_json: &str) -> Result<String, JsError> {
-    let fields: HashMap<String, String> =
-        serde_json::from_str(fields_json).map_err(|e| JsError::new(&format!("Invalid JSON: {}", e)))?;
+pub fn render_template(template_str: &str, fields_json: &str) -> Result<String, JsValue> {
+    let fields = parse_fields(fields_json)?;
 
-    template::render(template_str, &fields).map_err(|e| JsError::new(&e))
+    template::render(template_str, &fields).map_err(|e| template_error_to_js(&e))
 }
 
 /// Render an Anki cloze card template with the given fields.
@@ -31,18 +75,225 @@ pub fn render_template(template_str: &str, fields_json: &str) -> Result<String,
 ///
 /// # Returns
 /// The rendered HTML string with cloze deletions processed
+///
+/// Template errors are thrown as in `render_template`.
 #[wasm_bindgen]
 pub fn render_cloze_card(
     template_str: &str,
     fields_json: &str,
     card_ord: u32,
     is_question: bool,
-) -> Result<String, JsError> {
-    let fields: HashMap<String, String> =
-        serde_json::from_str(fields_json).map_err(|e| JsError::new(&format!("Invalid JSON: {}", e)))?;
+) -> Result<String, JsValue> {
+    let fields = parse_fields(fields_json)?;
 
     template::render_with_cloze(template_str, &fields, card_ord, is_question)
-        .map_err(|e| JsError::new(&e))
+        .map_err(|e| template_error_to_js(&e))
+}
+
+/// A card template parsed once, for rendering many notes.
+///
+/// `render_template` and `render_cloze_card` parse the template on every
+/// call; when rendering many notes of one note type, compile it once
+/// instead. Call `free()` when done to release the WASM memory.
+#[wasm_bindgen(js_name = CompiledTemplate)]
+pub struct JsCompiledTemplate {
+    inner: CompiledTemplate,
+}
+
+#[wasm_bindgen(js_class = CompiledTemplate)]
+impl JsCompiledTemplate {
+    /// Parse a card template.
+    ///
+    /// Template errors are thrown as in `render_template`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(template_str: &str) -> Result<JsCompiledTemplate, JsValue> {
+        let inner = CompiledTemplate::new(template_str).map_err(|e| template_error_to_js(&e))?;
+        Ok(JsCompiledTemplate { inner })
+    }
+
+    /// Render with the given fields.
+    ///
+    /// # Arguments
+    /// * `fields_json` - JSON object mapping field names to values
+    pub fn render(&self, fields_json: &str) -> Result<String, JsValue> {
+        Ok(self.inner.render(&parse_fields(fields_json)?))
+    }
+
+    /// Render one side of a cloze card.
+    ///
+    /// # Arguments
+    /// * `fields_json` - JSON object mapping field names to values
+    /// * `card_ord` - The card ordinal (1-indexed) determining which cloze is active
+    /// * `is_question` - Whether rendering for question (front) or answer (back) side
+    pub fn render_cloze(
+        &self,
+        fields_json: &str,
+        card_ord: u32,
+        is_question: bool,
+    ) -> Result<String, JsValue> {
+        let fields = parse_fields(fields_json)?;
+        Ok(self.inner.render_cloze(&fields, card_ord, is_question))
+    }
+}
+
+/// Render both sides of a card.
+///
+/// # Arguments
+/// * `front` - The question template
+/// * `back` - The answer template
+/// * `fields_json` - JSON object mapping field names to values
+/// * `card_ord` - The cloze card ordinal (1-indexed), or 0 for non-cloze cards
+/// * `typed_answer` - What the user typed into the `{{type:...}}` input, if
+///   the answer side should grade it
+///
+/// # Returns
+/// A JSON object `{ question, answer, questionIsEmpty }`, the last telling
+/// whether Anki would skip the card for its blank front. The answer
+/// template sees the question as `{{FrontSide}}`, with its audio removed. With a typed
+/// answer, `{{type:...}}` fields on the answer side show Anki's comparison
+/// markup instead of an input.
+///
+/// Template errors are thrown as in `render_template`.
+#[wasm_bindgen(js_name = render_card)]
+pub fn render_card_js(
+    front: &str,
+    back: &str,
+    fields_json: &str,
+    card_ord: u32,
+    typed_answer: Option<String>,
+) -> Result<String, JsValue> {
+    let fields = parse_fields(fields_json)?;
+    let templates = CardTemplates::new(front, back).map_err(|e| template_error_to_js(&e))?;
+    let card = match typed_answer {
+        Some(typed) => templates.render_with_typed_answer(&fields, card_ord, &typed),
+        None => templates.render(&fields, card_ord),
+    };
+    Ok(serde_json::to_string(&card).unwrap_or_default())
+}
+
+/// Render every card of many notes of one note type in a single call.
+///
+/// # Arguments
+/// * `note_type_json` - JSON object `{ front, back, css }` with the note type's templates
+/// * `notes_json` - JSON array of objects mapping field names to values
+///
+/// # Returns
+/// A JSON document `{ css, cards }`, where `cards` lists
+/// `{ noteIndex, ord, question, answer, questionIsEmpty }` for each
+/// generated card: one per standard note, and one per cloze number for
+/// cloze notes
+///
+/// Template errors are thrown as in `render_template`.
+#[wasm_bindgen(js_name = render_deck)]
+pub fn render_deck_js(note_type_json: &str, notes_json: &str) -> Result<String, JsValue> {
+    let note_type: NoteType = serde_json::from_str(note_type_json)
+        .map_err(|e| JsError::new(&format!("Invalid note type JSON: {}", e)))?;
+    let notes: Vec<HashMap<String, String>> = serde_json::from_str(notes_json)
+        .map_err(|e| JsError::new(&format!("Invalid JSON: {}", e)))?;
+
+    let cards = card::render_deck(&note_type, &notes).map_err(|e| template_error_to_js(&e))?;
+    Ok(serde_json::json!({ "css": note_type.css, "cards": cards }).to_string())
+}
+
+/// List the cards Anki would generate for a note.
+///
+/// # Arguments
+/// * `front_templates_json` - JSON array of the note type's question templates, in order
+/// * `fields_json` - JSON object mapping field names to values
+///
+/// # Returns
+/// For standard note types, the 0-based indices of the templates whose
+/// front is not blank; for cloze note types, the 1-indexed cloze numbers
+/// in the fields rendered with `{{cloze:...}}`
+///
+/// Template errors are thrown as in `render_template`.
+#[wasm_bindgen(js_name = generate_cards)]
+pub fn generate_cards_js(
+    front_templates_json: &str,
+    fields_json: &str,
+) -> Result<Vec<u32>, JsValue> {
+    let fronts: Vec<String> = serde_json::from_str(front_templates_json)
+        .map_err(|e| JsError::new(&format!("Invalid templates JSON: {}", e)))?;
+    let fields = parse_fields(fields_json)?;
+
+    let fronts = fronts
+        .iter()
+        .map(|front| CompiledTemplate::new(front))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| template_error_to_js(&e))?;
+    Ok(card::generate_cards(&fronts, &fields))
+}
+
+/// Check a template for errors without rendering it.
+///
+/// Parsing continues past each error, so every problem in the template is
+/// reported at once.
+///
+/// # Arguments
+/// * `template_str` - The card template string to check
+///
+/// # Returns
+/// An array of `Error` objects with the same location properties as errors
+/// thrown by `render_template`, ordered by position; empty if the template
+/// is valid
+#[wasm_bindgen]
+pub fn validate_template(template_str: &str) -> js_sys::Array {
+    let (_, errors) = template::parse_template_recovering(template_str);
+    errors.iter().map(template_error_to_js).collect()
+}
+
+/// Parse a template and return its node tree as JSON.
+///
+/// # Arguments
+/// * `template_str` - The card template string to parse
+///
+/// # Returns
+/// A JSON document `{ version, nodes, errors }` describing every node's
+/// kind, field name, filters, negation and source spans. Templates with
+/// errors still produce the partial tree, with the errors listed.
+#[wasm_bindgen]
+pub fn parse_template_ast(template_str: &str) -> String {
+    ast::template_ast_json(template_str)
+}
+
+/// Rename a field in a template.
+///
+/// # Arguments
+/// * `template_str` - The card template string
+/// * `old_name` - The field's current name
+/// * `new_name` - The field's new name
+///
+/// # Returns
+/// The template with every `{{Old}}`, `{{filter:Old}}`, `{{#Old}}`,
+/// `{{^Old}}` and `{{/Old}}` tag renamed; text is left untouched.
+///
+/// Template errors are thrown as in `render_template`. An invalid
+/// `new_name` throws a plain `Error` without location properties.
+#[wasm_bindgen(js_name = rename_field)]
+pub fn rename_field_js(
+    template_str: &str,
+    old_name: &str,
+    new_name: &str,
+) -> Result<String, JsValue> {
+    template::rename_field(template_str, old_name, new_name).map_err(|e| match e {
+        RenameFieldError::Template(err) => template_error_to_js(&err),
+        RenameFieldError::InvalidFieldName(_) => JsError::new(&e.to_string()).into(),
+    })
+}
+
+/// List the cloze numbers used across one or more fields.
+///
+/// # Arguments
+/// * `fields_json` - JSON array of field contents containing cloze syntax
+///
+/// # Returns
+/// The cloze numbers, sorted and without duplicates; each is the ordinal
+/// of a card the note generates
+#[wasm_bindgen]
+pub fn cloze_ordinals(fields_json: &str) -> Result<Vec<u32>, JsValue> {
+    let fields: Vec<String> = serde_json::from_str(fields_json)
+        .map_err(|e| JsError::new(&format!("Invalid JSON: {}", e)))?;
+    Ok(cloze::cloze_ordinals(fields.iter().map(String::as_str)))
 }
 
 /// Count the number of cloze cards a field generates.
@@ -51,12 +302,28 @@ pub fn render_cloze_card(
 /// * `field_content` - The field content containing cloze syntax
 ///
 /// # Returns
-/// The number of unique cloze ordinals (number of cards)
+/// The number of unique cloze ordinals (number of cards), i.e. the length
+/// of `cloze_ordinals` for the field
 #[wasm_bindgen]
 pub fn count_cloze_cards(field_content: &str) -> u32 {
     cloze::count_cloze_ordinals(field_content)
 }
 
+/// Compare a typed answer with the expected one, as shown on the answer side
+/// of a `{{type:...}}` card.
+///
+/// # Arguments
+/// * `expected` - The expected answer; HTML is reduced to plain text
+/// * `typed` - What the user typed
+///
+/// # Returns
+/// Anki's `<code id=typeans>` markup, with `typeGood`, `typeBad` and
+/// `typeMissed` spans marking the character-level differences
+#[wasm_bindgen]
+pub fn compare_answer(expected: &str, typed: &str) -> String {
+    typeans::compare_answer(expected, typed)
+}
+
 /// Get the library version
 #[wasm_bindgen]
 pub fn version() -> String {
@@ -110,7 +377,8 @@ mod tests {
             template::render_with_cloze("{{cloze:Text}}", &fields, 1, true).unwrap();
         assert_eq!(
             result,
-            "<span class=\"cloze\">[...]</span> is the capital of France"
+            "<span class=\"cloze\" data-cloze=\"Paris\" data-ordinal=\"1\">[...]</span> \
+             is the capital of <span class=\"cloze-inactive\" data-ordinal=\"2\">France</span>"
         );
     }
 
@@ -125,7 +393,8 @@ mod tests {
             template::render_with_cloze("{{cloze:Text}}", &fields, 1, false).unwrap();
         assert_eq!(
             result,
-            "<span class=\"cloze\">Paris</span> is the capital of France"
+            "<span class=\"cloze\" data-ordinal=\"1\">Paris</span> is the capital of \
+             <span class=\"cloze-inactive\" data-ordinal=\"2\">France</span>"
         );
     }
 
@@ -140,7 +409,26 @@ mod tests {
             template::render_with_cloze("{{cloze:Text}}", &fields, 1, true).unwrap();
         assert_eq!(
             result,
-            "<span class=\"cloze\">[capital city]</span> is in France"
+            "<span class=\"cloze\" data-cloze=\"Paris\" data-ordinal=\"1\">[capital city]</span> \
+             is in France"
+        );
+    }
+
+    #[test]
+    fn test_cloze_only_via_template() {
+        let mut fields = HashMap::new();
+        fields.insert(
+            "Text".to_string(),
+            "{{c1::<b>Paris</b>}} is the capital of {{c2::France}}".to_string(),
+        );
+        let template = "[{{text:cloze-only:Text}}]";
+        assert_eq!(
+            template::render_with_cloze(template, &fields, 1, true).unwrap(),
+            "[...]"
+        );
+        assert_eq!(
+            template::render_with_cloze(template, &fields, 1, false).unwrap(),
+            "[Paris]"
         );
     }
 }
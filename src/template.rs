@@ -1,189 +1,749 @@
 use crate::cloze;
+use crate::error::{RenameFieldError, TemplateError};
 use crate::filters;
+use crate::typeans;
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_until, take_while1},
-    character::complete::char,
-    combinator::{map, recognize},
-    multi::many0,
-    sequence::{delimited, pair},
-    IResult,
+    bytes::complete::tag,
+    character::complete::{char, multispace0, satisfy},
+    combinator::{all_consuming, map, not, recognize, verify},
+    multi::{many0, many1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult, Slice,
 };
-use nom_locate::LocatedSpan;
+use nom_locate::{position, LocatedSpan};
+use regex::Regex;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::sync::LazyLock;
 
 /// Input type with position tracking
 type Span<'a> = LocatedSpan<&'a str>;
 
-/// A parsed template node
-#[derive(Debug, Clone, PartialEq)]
+/// A position in the template source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SourcePos {
+    /// Byte offset from the start of the template
+    pub offset: usize,
+    /// Line number (1-indexed)
+    pub line: u32,
+    /// Column in characters (1-indexed)
+    pub column: usize,
+}
+
+impl SourcePos {
+    fn from_span(span: Span) -> Self {
+        SourcePos {
+            offset: span.location_offset(),
+            line: span.location_line(),
+            column: span.get_utf8_column(),
+        }
+    }
+}
+
+/// A range of the template source, from `start` up to (not including) `end`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SourceSpan {
+    pub start: SourcePos,
+    pub end: SourcePos,
+}
+
+impl SourceSpan {
+    /// The range covered by a parsed span
+    fn of(span: Span) -> Self {
+        SourceSpan {
+            start: SourcePos::from_span(span),
+            end: SourcePos::from_span(span.slice(span.fragment().len()..)),
+        }
+    }
+
+    /// The range between two parser positions
+    fn between(start: Span, end: Span) -> Self {
+        SourceSpan {
+            start: SourcePos::from_span(start),
+            end: SourcePos::from_span(end),
+        }
+    }
+}
+
+/// Location of a tag and of the field name inside it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TagSpan {
+    /// The whole tag, including delimiters
+    pub tag: SourceSpan,
+    /// The field name, without surrounding whitespace
+    pub name: SourceSpan,
+}
+
+/// A parsed template node.
+///
+/// Each node records where it came from in the template source. Nodes built
+/// programmatically with [`TemplateNode::text`], [`TemplateNode::field`] and
+/// [`TemplateNode::conditional`] have default (zero) spans.
+///
+/// Equality compares structure only and ignores spans, so the same template
+/// formatted differently (e.g. `{{ Front }}` and `{{Front}}`) parses to equal
+/// trees.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase", rename_all_fields = "camelCase")]
 pub enum TemplateNode {
     /// Plain text content
-    Text(String),
+    Text { text: String, span: SourceSpan },
     /// Field substitution: {{FieldName}} or {{filter:FieldName}}
     Field {
         name: String,
         filters: Vec<String>,
+        span: TagSpan,
+        /// Location of each filter name, in the same order as `filters`
+        filter_spans: Vec<SourceSpan>,
     },
     /// Conditional block: {{#Field}}...{{/Field}}
     Conditional {
         field: String,
         children: Vec<TemplateNode>,
         is_negative: bool,
+        open: TagSpan,
+        /// The close tag. A block closed implicitly while recovering from
+        /// an error has empty spans where it was closed.
+        close: TagSpan,
     },
 }
 
-/// Parse a field name (alphanumeric, spaces, and underscores)
-fn field_name(input: Span) -> IResult<Span, Span> {
-    take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == ' ')(input)
+impl TemplateNode {
+    /// Build a text node
+    pub fn text(text: &str) -> Self {
+        TemplateNode::Text {
+            text: text.to_string(),
+            span: SourceSpan::default(),
+        }
+    }
+
+    /// Build a field node; `filters` are listed as written, outermost first
+    pub fn field(name: &str, filters: &[&str]) -> Self {
+        TemplateNode::Field {
+            name: name.to_string(),
+            filters: filters.iter().map(|s| s.to_string()).collect(),
+            span: TagSpan::default(),
+            filter_spans: vec![SourceSpan::default(); filters.len()],
+        }
+    }
+
+    /// Build a conditional block node
+    pub fn conditional(field: &str, is_negative: bool, children: Vec<TemplateNode>) -> Self {
+        TemplateNode::Conditional {
+            field: field.to_string(),
+            children,
+            is_negative,
+            open: TagSpan::default(),
+            close: TagSpan::default(),
+        }
+    }
+
+    /// The source range covered by the node; for a conditional, from the
+    /// start of its open tag to the end of its close tag
+    pub fn span(&self) -> SourceSpan {
+        match self {
+            TemplateNode::Text { span, .. } => *span,
+            TemplateNode::Field { span, .. } => span.tag,
+            TemplateNode::Conditional { open, close, .. } => SourceSpan {
+                start: open.tag.start,
+                end: close.tag.end,
+            },
+        }
+    }
 }
 
-/// Parse filter chain: filter1:filter2:FieldName
-fn filter_chain(input: Span<'_>) -> IResult<Span<'_>, (Vec<&str>, &str)> {
-    let (input, parts) = recognize(pair(
-        many0(pair(
-            take_while1(|c: char| c.is_alphanumeric() || c == '-' || c == '_'),
-            char(':'),
-        )),
-        field_name,
-    ))(input)?;
-
-    let segments: Vec<&str> = parts.fragment().split(':').collect();
-    if segments.len() > 1 {
-        let filters = segments[..segments.len() - 1].to_vec();
-        let name = segments[segments.len() - 1];
-        Ok((input, (filters, name)))
-    } else {
-        Ok((input, (vec![], segments[0])))
+impl PartialEq for TemplateNode {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (TemplateNode::Text { text: a, .. }, TemplateNode::Text { text: b, .. }) => a == b,
+            (
+                TemplateNode::Field {
+                    name: a,
+                    filters: a_filters,
+                    ..
+                },
+                TemplateNode::Field {
+                    name: b,
+                    filters: b_filters,
+                    ..
+                },
+            ) => a == b && a_filters == b_filters,
+            (
+                TemplateNode::Conditional {
+                    field: a,
+                    children: a_children,
+                    is_negative: a_negative,
+                    ..
+                },
+                TemplateNode::Conditional {
+                    field: b,
+                    children: b_children,
+                    is_negative: b_negative,
+                    ..
+                },
+            ) => a == b && a_negative == b_negative && a_children == b_children,
+            _ => false,
+        }
     }
 }
 
-/// Parse a field substitution: {{FieldName}} or {{filter:FieldName}}
-fn parse_field(input: Span) -> IResult<Span, TemplateNode> {
-    let (input, (filters, name)) = delimited(tag("{{"), filter_chain, tag("}}"))(input)?;
+/// Delimiters around a tag
+#[derive(Debug, Clone, Copy)]
+struct Delimiters {
+    open: &'static str,
+    close: &'static str,
+}
 
-    Ok((
-        input,
-        TemplateNode::Field {
-            name: name.trim().to_string(),
-            filters: filters.iter().map(|s| s.to_string()).collect(),
+/// Standard `{{Field}}` tags
+const BRACES: Delimiters = Delimiters {
+    open: "{{",
+    close: "}}",
+};
+
+/// Legacy `<%Field%>` tags
+const LEGACY: Delimiters = Delimiters {
+    open: "<%",
+    close: "%>",
+};
+
+/// Directive that switches a template to the legacy alternate syntax. It
+/// must start the template, and enables `<%...%>` tags in addition to
+/// `{{...}}` for the rest of it, as in Anki.
+const ALT_SYNTAX_DIRECTIVE: &str = "{{=<% %>=}}";
+
+/// Whether a character may appear in a field or filter name.
+///
+/// Anki reserves `:` (filter separator), `{`, `}` and `"`; everything else,
+/// including punctuation, spaces and non-Latin scripts, is allowed.
+fn is_name_char(c: char) -> bool {
+    !matches!(c, ':' | '{' | '}' | '"')
+}
+
+/// Parse a run of name characters, stopping before the close delimiter
+fn name_chars(input: Span, delims: Delimiters) -> IResult<Span, Span> {
+    recognize(many1(preceded(not(tag(delims.close)), satisfy(is_name_char))))(input)
+}
+
+/// Parse a field name, including any surrounding whitespace. Once trimmed,
+/// the name must be non-empty and may not start with `#`, `^` or `/`, which
+/// would make the tag a conditional.
+fn field_name(input: Span, delims: Delimiters) -> IResult<Span, Span> {
+    verify(
+        |i| name_chars(i, delims),
+        |name: &Span| {
+            let name = name.fragment().trim();
+            !name.is_empty() && !name.starts_with(['#', '^', '/'])
         },
-    ))
+    )(input)
 }
 
-/// Parse a conditional open tag: {{#Field}} or {{^Field}}
-fn parse_conditional_open(input: Span<'_>) -> IResult<Span<'_>, (bool, &str)> {
-    let (input, _) = tag("{{")(input)?;
-    let (input, neg) = alt((map(char('#'), |_| false), map(char('^'), |_| true)))(input)?;
-    let (input, name) = field_name(input)?;
-    let (input, _) = tag("}}")(input)?;
-    Ok((input, (neg, *name.fragment())))
+/// Narrow a span to its contents without surrounding whitespace
+fn trim_span(span: Span) -> Span {
+    let fragment = *span.fragment();
+    let start = fragment.len() - fragment.trim_start().len();
+    let end = fragment.trim_end().len().max(start);
+    span.slice(start..end)
 }
 
-/// Parse a complete conditional block
-fn parse_conditional(input: Span) -> IResult<Span, TemplateNode> {
-    let (input, (is_negative, field)) = parse_conditional_open(input)?;
-    let close_tag = format!("{{{{/{}}}}}", field);
+/// Parse filter chain: filter1:filter2:FieldName
+///
+/// Whitespace around each filter and the field name is trimmed, so
+/// `{{ text: Field }}` is the same as `{{text:Field}}`.
+fn filter_chain(input: Span<'_>, delims: Delimiters) -> IResult<Span<'_>, (Vec<Span<'_>>, Span<'_>)> {
+    let (input, (filters, name)) = pair(
+        many0(terminated(|i| name_chars(i, delims), char(':'))),
+        |i| field_name(i, delims),
+    )(input)?;
+    let filters = filters.into_iter().map(trim_span).collect();
+    Ok((input, (filters, trim_span(name))))
+}
 
-    // Find where the close tag is
-    let (input, inner) = take_until(close_tag.as_str())(input)?;
-    let (input, _) = tag(close_tag.as_str())(input)?;
+/// A lexical token: a run of text or a single `{{...}}` tag.
+///
+/// Field and filter names are trimmed of surrounding whitespace.
+#[derive(Debug)]
+enum Token<'a> {
+    Text(Span<'a>),
+    Field { name: Span<'a>, filters: Vec<Span<'a>> },
+    Open { field: Span<'a>, is_negative: bool },
+    Close { field: Span<'a> },
+}
 
-    // Parse the inner content recursively
-    let (_, children) = parse_template_nodes(inner)?;
+/// Parse a field substitution: {{FieldName}} or {{filter:FieldName}}
+fn parse_field(input: Span<'_>, delims: Delimiters) -> IResult<Span<'_>, Token<'_>> {
+    let (input, (filters, name)) = delimited(
+        tag(delims.open),
+        |i| filter_chain(i, delims),
+        tag(delims.close),
+    )(input)?;
+    Ok((input, Token::Field { name, filters }))
+}
 
+/// Parse a conditional open tag: {{#Field}} or {{^Field}}, allowing
+/// whitespace around the marker and name as in `{{ # Field }}`
+fn parse_conditional_open(input: Span<'_>, delims: Delimiters) -> IResult<Span<'_>, Token<'_>> {
+    let (input, _) = pair(tag(delims.open), multispace0)(input)?;
+    let (input, is_negative) =
+        alt((map(char('#'), |_| false), map(char('^'), |_| true)))(input)?;
+    let (input, name) = field_name(input, delims)?;
+    let (input, _) = tag(delims.close)(input)?;
     Ok((
         input,
-        TemplateNode::Conditional {
-            field: field.trim().to_string(),
-            children,
+        Token::Open {
+            field: trim_span(name),
             is_negative,
         },
     ))
 }
 
-/// Parse plain text (everything up to the next {{ or end)
-fn parse_text(input: Span) -> IResult<Span, TemplateNode> {
-    let (input, text) = alt((take_until("{{"), nom::combinator::rest))(input)?;
+/// Parse a conditional close tag: {{/Field}}, allowing whitespace as in
+/// `{{ / Field }}`
+fn parse_conditional_close(input: Span<'_>, delims: Delimiters) -> IResult<Span<'_>, Token<'_>> {
+    let (input, name) = delimited(
+        tuple((tag(delims.open), multispace0, char('/'))),
+        |i| field_name(i, delims),
+        tag(delims.close),
+    )(input)?;
+    Ok((
+        input,
+        Token::Close {
+            field: trim_span(name),
+        },
+    ))
+}
+
+/// Parse a single tag using the given delimiters
+fn parse_tag(input: Span<'_>, delims: Delimiters) -> IResult<Span<'_>, Token<'_>> {
+    alt((
+        |i| parse_conditional_open(i, delims),
+        |i| parse_conditional_close(i, delims),
+        |i| parse_field(i, delims),
+    ))(input)
+}
+
+/// Parse plain text: everything up to the next tag opening or the end
+fn parse_text<'a>(input: Span<'a>, syntax: &[Delimiters]) -> IResult<Span<'a>, Token<'a>> {
+    let fragment = *input.fragment();
+    let end = syntax
+        .iter()
+        .filter_map(|delims| fragment.find(delims.open))
+        .min()
+        .unwrap_or(fragment.len());
 
-    if text.fragment().is_empty() {
+    if end == 0 {
         Err(nom::Err::Error(nom::error::Error::new(
             input,
             nom::error::ErrorKind::TakeWhile1,
         )))
     } else {
-        Ok((input, TemplateNode::Text(text.fragment().to_string())))
+        Ok((input.slice(end..), Token::Text(input.slice(..end))))
+    }
+}
+
+/// Parse a single token, accepting tags in any of the `syntax` delimiters
+fn parse_token<'a>(input: Span<'a>, syntax: &[Delimiters]) -> IResult<Span<'a>, Token<'a>> {
+    for &delims in syntax {
+        if let Ok(parsed) = parse_tag(input, delims) {
+            return Ok(parsed);
+        }
+    }
+    parse_text(input, syntax)
+}
+
+/// A token with the positions where it starts and ends
+type Located<'a> = (Span<'a>, Token<'a>, Span<'a>);
+
+/// Split the template into tokens, each with its start and end position.
+///
+/// Stops at the first tag opening that does not begin a valid tag.
+fn tokenize<'a>(input: Span<'a>, syntax: &[Delimiters]) -> IResult<Span<'a>, Vec<Located<'a>>> {
+    many0(tuple((position, |i| parse_token(i, syntax), position)))(input)
+}
+
+/// Parse the legacy alternate syntax directive, with any leading whitespace
+fn alt_syntax_directive(input: Span<'_>) -> IResult<Span<'_>, Span<'_>> {
+    recognize(pair(multispace0, tag(ALT_SYNTAX_DIRECTIVE)))(input)
+}
+
+/// Skip a tag opening that does not begin a valid tag, returning the
+/// remaining input and a description of the problem.
+///
+/// The broken tag runs through the next close delimiter, unless another tag
+/// opening comes first, in which case only the opening is skipped.
+fn skip_broken_tag<'a>(input: Span<'a>, syntax: &[Delimiters]) -> (Span<'a>, String) {
+    let delims = syntax
+        .iter()
+        .find(|delims| input.fragment().starts_with(delims.open))
+        .copied()
+        .unwrap_or(BRACES);
+    let skip = delims.open.len();
+    let body = &input.fragment()[skip..];
+    let next_open = body.find(delims.open);
+    match body.find(delims.close) {
+        Some(close) if next_open.is_none_or(|open| close < open) => (
+            input.slice(skip + close + delims.close.len()..),
+            "invalid tag".to_string(),
+        ),
+        _ => (
+            input.slice(skip..),
+            format!("tag is missing closing '{}'", delims.close),
+        ),
+    }
+}
+
+/// Tokenize the whole template, recording an error for each broken tag and
+/// continuing after it. Also returns the span at the end of the template.
+fn tokenize_recovering<'a>(
+    source: &'a str,
+    errors: &mut Vec<TemplateError>,
+) -> (Vec<Located<'a>>, Span<'a>) {
+    let mut tokens = Vec::new();
+    let (mut input, syntax): (Span, &[Delimiters]) = match alt_syntax_directive(Span::new(source)) {
+        Ok((rest, _)) => (rest, &[BRACES, LEGACY]),
+        Err(_) => (Span::new(source), &[BRACES]),
+    };
+
+    loop {
+        // Tag and text parsers only return recoverable errors, which many0
+        // treats as the end of the sequence
+        let (remaining, parsed) = tokenize(input, syntax).unwrap_or((input, Vec::new()));
+        tokens.extend(parsed);
+        if remaining.fragment().is_empty() {
+            return (tokens, remaining);
+        }
+
+        let (after, description) = skip_broken_tag(remaining, syntax);
+        errors.push(TemplateError::syntax(
+            &description,
+            SourcePos::from_span(remaining),
+            source,
+        ));
+        input = after;
+    }
+}
+
+/// A conditional block whose close tag has not been seen yet
+struct OpenBlock<'a> {
+    field: Span<'a>,
+    is_negative: bool,
+    open: TagSpan,
+    children: Vec<TemplateNode>,
+    /// Whether a mismatched close tag has already been reported against it
+    reported: bool,
+}
+
+/// Add a node to the innermost open block, or to the top level
+fn push_node(stack: &mut [OpenBlock], root: &mut Vec<TemplateNode>, node: TemplateNode) {
+    match stack.last_mut() {
+        Some(block) => block.children.push(node),
+        None => root.push(node),
+    }
+}
+
+/// Close the innermost open block at `close`, attaching it to its parent
+fn close_innermost(stack: &mut Vec<OpenBlock>, root: &mut Vec<TemplateNode>, close: TagSpan) {
+    if let Some(block) = stack.pop() {
+        let node = TemplateNode::Conditional {
+            field: block.field.fragment().to_string(),
+            children: block.children,
+            is_negative: block.is_negative,
+            open: block.open,
+            close,
+        };
+        push_node(stack, root, node);
+    }
+}
+
+/// An empty tag span at `pos`, for blocks closed without a close tag
+fn implicit_close(pos: Span) -> TagSpan {
+    let span = SourceSpan::between(pos, pos);
+    TagSpan {
+        tag: span,
+        name: span,
+    }
+}
+
+/// Build the node tree from a token stream.
+///
+/// Open tags are pushed onto a stack and each close tag pops the innermost
+/// open block, so blocks may be nested arbitrarily, including blocks for the
+/// same field.
+///
+/// Block structure errors are recorded and the tree repaired as it goes: a
+/// close tag for an outer block also closes the blocks inside it, a close
+/// tag matching no open block is ignored, and blocks still open at the end
+/// of the template are closed there. Each mistake is reported once, so a
+/// block whose close tag was misspelled is not also reported as unclosed,
+/// and the close tag of a block already closed by an outer one, as in
+/// `{{#A}}{{#B}}{{/A}}{{/B}}`, is not also reported as unopened.
+fn build_tree<'a>(
+    source: &str,
+    tokens: Vec<Located<'a>>,
+    end: Span<'a>,
+    errors: &mut Vec<TemplateError>,
+) -> Vec<TemplateNode> {
+    let mut root = Vec::new();
+    let mut stack: Vec<OpenBlock> = Vec::new();
+    // Blocks closed by a mismatched close tag, whose own close tag may follow
+    let mut implicitly_closed: Vec<&str> = Vec::new();
+
+    for (start, token, token_end) in tokens {
+        match token {
+            Token::Text(text) => {
+                let node = TemplateNode::Text {
+                    text: text.fragment().to_string(),
+                    span: SourceSpan::of(text),
+                };
+                push_node(&mut stack, &mut root, node);
+            }
+            Token::Field { name, filters } => {
+                let node = TemplateNode::Field {
+                    name: name.fragment().to_string(),
+                    filters: filters.iter().map(|s| s.fragment().to_string()).collect(),
+                    span: TagSpan {
+                        tag: SourceSpan::between(start, token_end),
+                        name: SourceSpan::of(name),
+                    },
+                    filter_spans: filters.into_iter().map(SourceSpan::of).collect(),
+                };
+                push_node(&mut stack, &mut root, node);
+            }
+            Token::Open { field, is_negative } => {
+                stack.push(OpenBlock {
+                    field,
+                    is_negative,
+                    open: TagSpan {
+                        tag: SourceSpan::between(start, token_end),
+                        name: SourceSpan::of(field),
+                    },
+                    children: Vec::new(),
+                    reported: false,
+                });
+            }
+            Token::Close { field } => {
+                let name = *field.fragment();
+                if !stack.iter().any(|block| *block.field.fragment() == name) {
+                    if let Some(index) = implicitly_closed.iter().rposition(|&n| n == name) {
+                        // Already reported with the close tag that closed it
+                        implicitly_closed.remove(index);
+                        continue;
+                    }
+                }
+                if let Some(innermost) = stack
+                    .last_mut()
+                    .filter(|block| *block.field.fragment() != name)
+                {
+                    innermost.reported = true;
+                    errors.push(TemplateError::mismatched_close(
+                        innermost.field.fragment(),
+                        innermost.is_negative,
+                        innermost.open.tag.start,
+                        name,
+                        SourcePos::from_span(start),
+                        source,
+                    ));
+                }
+                match stack.iter().rposition(|block| *block.field.fragment() == name) {
+                    Some(index) => {
+                        while stack.len() > index + 1 {
+                            if let Some(block) = stack.last() {
+                                implicitly_closed.push(block.field.fragment());
+                            }
+                            close_innermost(&mut stack, &mut root, implicit_close(start));
+                        }
+                        let close = TagSpan {
+                            tag: SourceSpan::between(start, token_end),
+                            name: SourceSpan::of(field),
+                        };
+                        close_innermost(&mut stack, &mut root, close);
+                    }
+                    None if stack.is_empty() => {
+                        errors.push(TemplateError::unopened_close(
+                            name,
+                            SourcePos::from_span(start),
+                            source,
+                        ));
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
+    for block in stack.iter().filter(|block| !block.reported) {
+        errors.push(TemplateError::unclosed_block(
+            block.field.fragment(),
+            block.is_negative,
+            block.open.tag.start,
+            SourcePos::from_span(end),
+            source,
+        ));
     }
+    while !stack.is_empty() {
+        close_innermost(&mut stack, &mut root, implicit_close(end));
+    }
+
+    root
 }
 
-/// Parse a single template node
-fn parse_node(input: Span) -> IResult<Span, TemplateNode> {
-    alt((parse_conditional, parse_field, parse_text))(input)
+/// Parse a template string, recovering from errors.
+///
+/// Broken tags are skipped and block structure errors repaired, so this
+/// always returns a (possibly partial) node tree together with every error
+/// found, ordered by position.
+pub fn parse_template_recovering(template: &str) -> (Vec<TemplateNode>, Vec<TemplateError>) {
+    let mut errors = Vec::new();
+    let (tokens, end) = tokenize_recovering(template, &mut errors);
+    let nodes = build_tree(template, tokens, end, &mut errors);
+    errors.sort_by_key(|e| e.offset);
+    (nodes, errors)
 }
 
-/// Parse all template nodes
-fn parse_template_nodes(input: Span) -> IResult<Span, Vec<TemplateNode>> {
-    many0(parse_node)(input)
+/// Parse a template string into nodes, failing on the first error
+pub fn parse_template(template: &str) -> Result<Vec<TemplateNode>, TemplateError> {
+    let (nodes, errors) = parse_template_recovering(template);
+    match errors.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(nodes),
+    }
 }
 
-/// Format a parse error with line and column information
-fn format_parse_error(span: Span, description: &str) -> String {
-    let line = span.location_line();
-    let column = span.get_utf8_column();
-    format!(
-        "Parse error at line {}, column {}: {}",
-        line, column, description
-    )
+/// Print a node tree back to template source.
+///
+/// Text is written verbatim and tags in canonical form: brace delimiters,
+/// no padding, filters joined by `:`. The one exception is a field named
+/// `=<% %>=`, which is padded as `{{ =<% %>=}}` so it is not read back as
+/// the directive switching to legacy delimiters. For any tree returned by
+/// [`parse_template`], parsing the output yields an equal tree, so edited
+/// trees can be written back to a note type safely.
+pub fn print_template(nodes: &[TemplateNode]) -> String {
+    let mut out = String::new();
+    print_nodes(nodes, &mut out);
+    out
 }
 
-/// Parse a template string into nodes
-pub fn parse_template(template: &str) -> Result<Vec<TemplateNode>, String> {
-    let input = Span::new(template);
-    match parse_template_nodes(input) {
-        Ok((remaining, nodes)) => {
-            if !remaining.fragment().is_empty() {
-                Err(format_parse_error(
-                    remaining,
-                    &format!("unexpected content: {}", remaining.fragment()),
-                ))
-            } else {
-                Ok(nodes)
+fn print_nodes(nodes: &[TemplateNode], out: &mut String) {
+    for node in nodes {
+        match node {
+            TemplateNode::Text { text, .. } => out.push_str(text),
+            TemplateNode::Field { name, filters, .. } => {
+                out.push_str("{{");
+                let directive_name = &ALT_SYNTAX_DIRECTIVE[2..ALT_SYNTAX_DIRECTIVE.len() - 2];
+                if filters.is_empty() && name == directive_name {
+                    out.push(' ');
+                }
+                for filter in filters {
+                    out.push_str(filter);
+                    out.push(':');
+                }
+                out.push_str(name);
+                out.push_str("}}");
+            }
+            TemplateNode::Conditional {
+                field,
+                children,
+                is_negative,
+                ..
+            } => {
+                out.push_str(if *is_negative { "{{^" } else { "{{#" });
+                out.push_str(field);
+                out.push_str("}}");
+                print_nodes(children, out);
+                out.push_str("{{/");
+                out.push_str(field);
+                out.push_str("}}");
             }
         }
-        Err(e) => {
-            match e {
-                nom::Err::Error(err) | nom::Err::Failure(err) => {
-                    let description = match err.code {
-                        nom::error::ErrorKind::Tag => "expected closing braces '}}' or valid tag",
-                        nom::error::ErrorKind::TakeUntil => "unclosed tag or missing closing tag",
-                        nom::error::ErrorKind::Char => "unexpected character",
-                        nom::error::ErrorKind::TakeWhile1 => "expected field name",
-                        _ => "invalid template syntax",
-                    };
-                    Err(format_parse_error(err.input, description))
+    }
+}
+
+/// Rename a field everywhere a template refers to it.
+///
+/// Field substitutions (with or without filters) and the open and close
+/// tags of conditional blocks on `old` are renamed to `new`; text is left
+/// alone. Like Anki, the template is rewritten via [`print_template`], so
+/// tags come out in canonical form. A template that does not mention `old`
+/// is returned unchanged.
+///
+/// `new` must be a valid field name: non-empty, without surrounding
+/// whitespace, `:`, `{`, `}` or `"`, and not starting with `#`, `^` or
+/// `/`. Otherwise [`RenameFieldError::InvalidFieldName`] is returned.
+pub fn rename_field(template: &str, old: &str, new: &str) -> Result<String, RenameFieldError> {
+    if !is_valid_field_name(new) {
+        return Err(RenameFieldError::InvalidFieldName(new.to_string()));
+    }
+    let mut nodes = parse_template(template)?;
+    if rename_in_nodes(&mut nodes, old, new) {
+        Ok(print_template(&nodes))
+    } else {
+        Ok(template.to_string())
+    }
+}
+
+/// Whether `name` can be written in a tag and parsed back as the same name
+fn is_valid_field_name(name: &str) -> bool {
+    name.trim() == name && all_consuming(|i| field_name(i, BRACES))(Span::new(name)).is_ok()
+}
+
+/// Rename `old` to `new` in place, returning whether anything changed
+fn rename_in_nodes(nodes: &mut [TemplateNode], old: &str, new: &str) -> bool {
+    let mut changed = false;
+    for node in nodes {
+        match node {
+            TemplateNode::Text { .. } => {}
+            TemplateNode::Field { name, .. } => {
+                if name == old {
+                    *name = new.to_string();
+                    changed = true;
                 }
-                nom::Err::Incomplete(_) => {
-                    Err("Parse error: incomplete input".to_string())
+            }
+            TemplateNode::Conditional {
+                field, children, ..
+            } => {
+                if field == old {
+                    *field = new.to_string();
+                    changed = true;
                 }
+                changed |= rename_in_nodes(children, old, new);
             }
         }
     }
+    changed
+}
+
+/// Field content that Anki treats as empty: whitespace, `&nbsp;`, line
+/// breaks and empty `<div>`s
+static EMPTY_FIELD_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^(?:\s|&nbsp;|</?(?:br|div)\s*/?>)*$").unwrap());
+
+/// Whether field content counts as empty, by Anki's definition.
+///
+/// Editors leave markup such as `<br>` or `<div></div>` behind in fields
+/// that look empty, so these count as empty for `{{#Field}}`/`{{^Field}}`
+/// and for blank-card detection.
+pub fn field_is_empty(text: &str) -> bool {
+    EMPTY_FIELD_REGEX.is_match(text)
 }
 
-/// Render parsed template nodes with given fields
+/// Render parsed template nodes with given fields.
+///
+/// With a `typed_answer`, `{{type:...}}` fields show its comparison with
+/// the field instead of an input.
 pub fn render_nodes(
     nodes: &[TemplateNode],
     fields: &HashMap<String, String>,
     cloze_ctx: Option<&ClozeContext>,
+    typed_answer: Option<&str>,
 ) -> String {
     let mut output = String::new();
 
     for node in nodes {
         match node {
-            TemplateNode::Text(text) => {
+            TemplateNode::Text { text, .. } => {
                 output.push_str(text);
             }
-            TemplateNode::Field { name, filters } => {
+            TemplateNode::Field { name, filters, .. } => {
                 // Handle special fields
                 let mut value = match name.as_str() {
                     "FrontSide" => fields.get("FrontSide").cloned().unwrap_or_default(),
@@ -195,12 +755,25 @@ pub fn render_nodes(
 
                 // Apply filters in reverse order (right-to-left, innermost first)
                 // {{text:hint:Field}} means: apply hint first, then text
-                for filter in filters.iter().rev() {
-                    if filter == "cloze" {
-                        // Cloze filter needs special context handling
+                for (i, filter) in filters.iter().enumerate().rev() {
+                    if filter == "cloze" || filter == "cloze-only" {
+                        // Cloze filters need special context handling
                         if let Some(ctx) = cloze_ctx {
-                            value = cloze::render_cloze(&value, ctx.card_ord, ctx.is_question);
+                            value = if filter == "cloze" && i > 0 && filters[i - 1] == "type" {
+                                // {{type:cloze:Field}} expects the active deletions' text
+                                cloze::typed_cloze_answer(&value, ctx.card_ord)
+                            } else if filter == "cloze" {
+                                cloze::render_cloze(&value, ctx.card_ord, ctx.is_question)
+                            } else {
+                                cloze::reveal_cloze_text_only(
+                                    &value,
+                                    ctx.card_ord,
+                                    ctx.is_question,
+                                )
+                            };
                         }
+                    } else if let Some(typed) = typed_answer.filter(|_| filter == "type") {
+                        value = typeans::compare_answer(&value, typed);
                     } else {
                         value = filters::apply_filter(filter, &value);
                     }
@@ -212,9 +785,10 @@ pub fn render_nodes(
                 field,
                 children,
                 is_negative,
+                ..
             } => {
                 let field_value = fields.get(field).map(|s| s.as_str()).unwrap_or("");
-                let field_has_value = !field_value.is_empty();
+                let field_has_value = !field_is_empty(field_value);
 
                 let should_render = if *is_negative {
                     !field_has_value
@@ -223,7 +797,7 @@ pub fn render_nodes(
                 };
 
                 if should_render {
-                    output.push_str(&render_nodes(children, fields, cloze_ctx));
+                    output.push_str(&render_nodes(children, fields, cloze_ctx, typed_answer));
                 }
             }
         }
@@ -232,10 +806,67 @@ pub fn render_nodes(
     output
 }
 
+/// A template parsed once, for rendering many notes without re-parsing
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompiledTemplate {
+    nodes: Vec<TemplateNode>,
+}
+
+impl CompiledTemplate {
+    /// Parse `template`, failing on the first error
+    pub fn new(template: &str) -> Result<Self, TemplateError> {
+        Ok(CompiledTemplate {
+            nodes: parse_template(template)?,
+        })
+    }
+
+    /// The parsed node tree
+    pub fn nodes(&self) -> &[TemplateNode] {
+        &self.nodes
+    }
+
+    /// Render with the given fields
+    pub fn render(&self, fields: &HashMap<String, String>) -> String {
+        render_nodes(&self.nodes, fields, None, None)
+    }
+
+    /// Render one side of cloze card `card_ord` (1-indexed)
+    pub fn render_cloze(
+        &self,
+        fields: &HashMap<String, String>,
+        card_ord: u32,
+        is_question: bool,
+    ) -> String {
+        let ctx = ClozeContext {
+            card_ord,
+            is_question,
+        };
+        render_nodes(&self.nodes, fields, Some(&ctx), None)
+    }
+
+    /// Render one side of card `card_ord` (0 for standard cards, the
+    /// 1-indexed cloze number for cloze cards) as shown after answering:
+    /// each `{{type:...}}` field compares `typed_answer` with its expected
+    /// answer, in Anki's `<code id=typeans>` markup.
+    pub fn render_with_typed_answer(
+        &self,
+        fields: &HashMap<String, String>,
+        card_ord: u32,
+        is_question: bool,
+        typed_answer: &str,
+    ) -> String {
+        let ctx = ClozeContext {
+            card_ord,
+            is_question,
+        };
+        let cloze_ctx = if card_ord > 0 { Some(&ctx) } else { None };
+        render_nodes(&self.nodes, fields, cloze_ctx, Some(typed_answer))
+    }
+}
+
 /// Render a template string with the given fields
-pub fn render(template: &str, fields: &HashMap<String, String>) -> Result<String, String> {
-    let nodes = parse_template(template)?;
-    Ok(render_nodes(&nodes, fields, None))
+pub fn render(template: &str, fields: &HashMap<String, String>) -> Result<String, TemplateError> {
+    Ok(CompiledTemplate::new(template)?.render(fields))
 }
 
 /// Cloze rendering context
@@ -250,18 +881,14 @@ pub fn render_with_cloze(
     fields: &HashMap<String, String>,
     card_ord: u32,
     is_question: bool,
-) -> Result<String, String> {
-    let nodes = parse_template(template)?;
-    let ctx = ClozeContext {
-        card_ord,
-        is_question,
-    };
-    Ok(render_nodes(&nodes, fields, Some(&ctx)))
+) -> Result<String, TemplateError> {
+    Ok(CompiledTemplate::new(template)?.render_cloze(fields, card_ord, is_question))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::TemplateErrorKind;
 
     #[test]
     fn test_simple_field() {
@@ -346,7 +973,7 @@ mod tests {
         let nodes = parse_template("{{text:Field}}").unwrap();
         assert_eq!(nodes.len(), 1);
         match &nodes[0] {
-            TemplateNode::Field { name, filters } => {
+            TemplateNode::Field { name, filters, .. } => {
                 assert_eq!(name, "Field");
                 assert_eq!(filters, &vec!["text".to_string()]);
             }
@@ -368,6 +995,30 @@ mod tests {
         assert_eq!(render("{{Card}}", &fields).unwrap(), "Card 1");
     }
 
+    #[test]
+    fn test_conditional_html_empty_field() {
+        let template = "{{#Extra}}shown{{/Extra}}{{^Extra}}hidden{{/Extra}}";
+        let empties = [
+            " \n\t",
+            "<br>",
+            "<BR />",
+            "&nbsp;",
+            "\u{a0}",
+            "<div></div>",
+            "<div><br/></div>",
+        ];
+        for empty in empties {
+            let mut fields = HashMap::new();
+            fields.insert("Extra".to_string(), empty.to_string());
+            assert_eq!(render(template, &fields).unwrap(), "hidden", "{:?}", empty);
+        }
+        for value in ["0", "<b></b>", "<div>x</div>", "<img src=a.png>"] {
+            let mut fields = HashMap::new();
+            fields.insert("Extra".to_string(), value.to_string());
+            assert_eq!(render(template, &fields).unwrap(), "shown", "{:?}", value);
+        }
+    }
+
     // Filter integration tests
     #[test]
     fn test_text_filter_via_template() {
@@ -398,6 +1049,25 @@ mod tests {
         assert!(result.contains("data-expected=\"correct\""));
     }
 
+    #[test]
+    fn test_type_cloze_expects_active_deletions() {
+        let mut fields = HashMap::new();
+        fields.insert(
+            "Text".to_string(),
+            "{{c1::Paris}} is the capital of {{c2::\"France\"}}".to_string(),
+        );
+        assert_eq!(
+            render_with_cloze("{{type:cloze:Text}}", &fields, 2, true).unwrap(),
+            "<input type=\"text\" id=\"typeans\" class=\"type-answer\" \
+             data-expected=\"&quot;France&quot;\"/>"
+        );
+        assert_eq!(
+            render_with_cloze("{{type:cloze:Text}}", &fields, 1, false).unwrap(),
+            render("{{type:Answer}}", &HashMap::from([("Answer".into(), "Paris".into())]))
+                .unwrap()
+        );
+    }
+
     #[test]
     fn test_furigana_filter_via_template() {
         let mut fields = HashMap::new();
@@ -467,7 +1137,7 @@ mod tests {
         let nodes = parse_template("{{text:hint:Field}}").unwrap();
         assert_eq!(nodes.len(), 1);
         match &nodes[0] {
-            TemplateNode::Field { name, filters } => {
+            TemplateNode::Field { name, filters, .. } => {
                 assert_eq!(name, "Field");
                 assert_eq!(filters, &vec!["text".to_string(), "hint".to_string()]);
             }
@@ -475,12 +1145,694 @@ mod tests {
         }
     }
 
+    // Field name grammar tests
+    #[test]
+    fn test_field_names_from_shared_decks() {
+        let names = [
+            "Front-Extra",
+            "Notes (EN)",
+            "Example.1",
+            "Frage?",
+            "Größe",
+            "Word/Phrase",
+            "Card #",
+            "Back (optional) - 2",
+            "例文",
+            "Audio [sound]",
+            "it's",
+        ];
+        for name in names {
+            let mut fields = HashMap::new();
+            fields.insert(name.to_string(), "value".to_string());
+
+            let template = format!("{{{{{0}}}}}|{{{{text:{0}}}}}|{{{{#{0}}}}}yes{{{{/{0}}}}}", name);
+            assert_eq!(
+                render(&template, &fields).unwrap(),
+                "value|value|yes",
+                "field name {:?}",
+                name
+            );
+            let template = format!("{{{{^{0}}}}}no{{{{/{0}}}}}", name);
+            assert_eq!(render(&template, &fields).unwrap(), "", "field name {:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_filter_chain_with_punctuated_field_name() {
+        let nodes = parse_template("{{hint:text:Notes (EN)}}").unwrap();
+        assert_eq!(
+            nodes,
+            vec![TemplateNode::field("Notes (EN)", &["hint", "text"])]
+        );
+    }
+
+    #[test]
+    fn test_reserved_characters_rejected_in_field_names() {
+        for template in ["{{Bad\"Name}}", "{{Bad{Name}}", "{{Bad}Name}}"] {
+            assert!(parse_template(template).is_err(), "template {:?}", template);
+        }
+    }
+
+    #[test]
+    fn test_field_name_cannot_start_with_block_marker() {
+        assert!(parse_template("{{##A}}x{{/#A}}").is_err());
+        assert!(parse_template("{{#^A}}x{{/^A}}").is_err());
+        assert!(parse_template("{{#/A}}").is_err());
+    }
+
+    #[test]
+    fn test_field_name_with_marker_inside() {
+        let nodes = parse_template("{{#Card #2}}x{{/Card #2}}").unwrap();
+        assert_eq!(
+            nodes,
+            vec![TemplateNode::conditional(
+                "Card #2",
+                false,
+                vec![TemplateNode::text("x")]
+            )]
+        );
+    }
+
+    // Whitespace tolerance tests
+    #[test]
+    fn test_padded_tags_parse_like_unpadded() {
+        let padded = parse_template(
+            "{{ Front }}{{# Extra }}{{ text: Extra }}{{/ Extra }}{{ ^Hint}}-{{ / Hint }}",
+        )
+        .unwrap();
+        let unpadded =
+            parse_template("{{Front}}{{#Extra}}{{text:Extra}}{{/Extra}}{{^Hint}}-{{/Hint}}")
+                .unwrap();
+        assert_eq!(padded, unpadded);
+    }
+
+    #[test]
+    fn test_close_tag_matched_on_trimmed_name() {
+        let mut fields = HashMap::new();
+        fields.insert("Extra".to_string(), "x".to_string());
+
+        assert_eq!(render("{{# Extra}}a{{/Extra }}", &fields).unwrap(), "a");
+        assert_eq!(render("{{#Extra}}b{{/ Extra}}", &fields).unwrap(), "b");
+        assert_eq!(render("{{ #Extra }}c{{ /Extra }}", &fields).unwrap(), "c");
+    }
+
+    #[test]
+    fn test_padding_inside_multiword_name_preserved() {
+        let nodes = parse_template("{{  Notes  (EN) }}").unwrap();
+        assert_eq!(
+            nodes,
+            vec![TemplateNode::field("Notes  (EN)", &[])]
+        );
+    }
+
+    #[test]
+    fn test_padded_filter_chain_applies_filters() {
+        let mut fields = HashMap::new();
+        fields.insert("Field".to_string(), "<b>Bold</b>".to_string());
+
+        assert_eq!(render("{{ text : Field }}", &fields).unwrap(), "Bold");
+    }
+
+    #[test]
+    fn test_blank_tag_is_error() {
+        assert!(parse_template("{{ }}").is_err());
+        assert!(parse_template("{{# }}x{{/ }}").is_err());
+        assert!(parse_template("{{text: }}").is_err());
+    }
+
+    // Legacy alternate syntax tests
+    #[test]
+    fn test_legacy_syntax_matches_brace_syntax() {
+        let legacy = parse_template(
+            "{{=<% %>=}}<%Front%> <%#Extra%><%text:Extra%><%/Extra%><%^Hint%>-<%/Hint%>",
+        )
+        .unwrap();
+        let braces =
+            parse_template("{{Front}} {{#Extra}}{{text:Extra}}{{/Extra}}{{^Hint}}-{{/Hint}}")
+                .unwrap();
+        assert_eq!(legacy, braces);
+    }
+
+    #[test]
+    fn test_legacy_syntax_renders() {
+        let mut fields = HashMap::new();
+        fields.insert("Front".to_string(), "Q".to_string());
+        fields.insert("Back".to_string(), "A".to_string());
+
+        let template = "\n  {{=<% %>=}}<%Front%>/{{Back}}<% #Back %>!<% /Back %>";
+        assert_eq!(render(template, &fields).unwrap(), "Q/A!");
+    }
+
+    #[test]
+    fn test_legacy_tags_are_text_without_directive() {
+        let nodes = parse_template("<%Front%>").unwrap();
+        assert_eq!(nodes, vec![TemplateNode::text("<%Front%>")]);
+    }
+
+    #[test]
+    fn test_legacy_blocks_mix_delimiters() {
+        let mut fields = HashMap::new();
+        fields.insert("A".to_string(), "a".to_string());
+
+        let result = render("{{=<% %>=}}<%#A%>x{{/A}}{{#A}}y<%/A%>", &fields).unwrap();
+        assert_eq!(result, "xy");
+    }
+
+    #[test]
+    fn test_legacy_error_positions() {
+        let err = parse_template("{{=<% %>=}}\nok <%Front").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 4));
+        assert_eq!(err.message, "tag is missing closing '%>'");
+
+        let err = parse_template("{{=<% %>=}}<%#A%>x<%/B%>").unwrap_err();
+        assert_eq!(err.kind, TemplateErrorKind::MismatchedClose);
+        assert_eq!(err.column, 19);
+        assert_eq!(err.snippet, "<%/B%>");
+    }
+
+    #[test]
+    fn test_legacy_field_name_may_contain_percent() {
+        let nodes = parse_template("{{=<% %>=}}<%100% Sure%>").unwrap();
+        assert_eq!(
+            nodes,
+            vec![TemplateNode::field("100% Sure", &[])]
+        );
+    }
+
+    // Source span tests
+    fn pos(offset: usize, line: u32, column: usize) -> SourcePos {
+        SourcePos {
+            offset,
+            line,
+            column,
+        }
+    }
+
+    fn span(start: (usize, u32, usize), end: (usize, u32, usize)) -> SourceSpan {
+        SourceSpan {
+            start: pos(start.0, start.1, start.2),
+            end: pos(end.0, end.1, end.2),
+        }
+    }
+
+    #[test]
+    fn test_field_spans() {
+        let nodes = parse_template("Q: {{ hint : text:Front }}").unwrap();
+        match &nodes[..] {
+            [TemplateNode::Text { span: text_span, .. }, TemplateNode::Field {
+                span: field_span,
+                filter_spans,
+                ..
+            }] => {
+                assert_eq!(*text_span, span((0, 1, 1), (3, 1, 4)));
+                assert_eq!(field_span.tag, span((3, 1, 4), (26, 1, 27)));
+                assert_eq!(field_span.name, span((18, 1, 19), (23, 1, 24)));
+                assert_eq!(
+                    filter_spans,
+                    &vec![span((6, 1, 7), (10, 1, 11)), span((13, 1, 14), (17, 1, 18))]
+                );
+            }
+            other => panic!("Unexpected nodes {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_conditional_spans_across_lines() {
+        let nodes = parse_template("{{#Extra}}\n  {{Extra}}\n{{/ Extra }}").unwrap();
+        match &nodes[0] {
+            TemplateNode::Conditional {
+                open,
+                close,
+                children,
+                ..
+            } => {
+                assert_eq!(open.tag, span((0, 1, 1), (10, 1, 11)));
+                assert_eq!(open.name, span((3, 1, 4), (8, 1, 9)));
+                assert_eq!(close.tag, span((23, 3, 1), (35, 3, 13)));
+                assert_eq!(close.name, span((27, 3, 5), (32, 3, 10)));
+                assert_eq!(children[1].span(), span((13, 2, 3), (22, 2, 12)));
+                assert_eq!(nodes[0].span(), span((0, 1, 1), (35, 3, 13)));
+            }
+            other => panic!("Expected Conditional, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_spans_count_columns_in_characters() {
+        let nodes = parse_template("äöü {{Größe}}").unwrap();
+        match &nodes[1] {
+            TemplateNode::Field { span, .. } => {
+                assert_eq!(span.tag.start, pos(7, 1, 5));
+                assert_eq!(span.name.start, pos(9, 1, 7));
+                assert_eq!(span.name.end, pos(16, 1, 12));
+            }
+            other => panic!("Expected Field, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_legacy_tag_spans() {
+        let nodes = parse_template("{{=<% %>=}}<%Front%>").unwrap();
+        assert_eq!(nodes[0].span(), span((11, 1, 12), (20, 1, 21)));
+    }
+
+    #[test]
+    fn test_implicitly_closed_block_has_empty_close_span() {
+        let (nodes, _) = parse_template_recovering("{{#A}}{{#B}}x{{/A}}");
+        match &nodes[0] {
+            TemplateNode::Conditional {
+                close, children, ..
+            } => {
+                assert_eq!(close.tag, span((13, 1, 14), (19, 1, 20)));
+                match &children[0] {
+                    TemplateNode::Conditional { close, .. } => {
+                        assert_eq!(close.tag, span((13, 1, 14), (13, 1, 14)));
+                    }
+                    other => panic!("Expected Conditional, got {:?}", other),
+                }
+            }
+            other => panic!("Expected Conditional, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_equality_ignores_spans() {
+        assert_eq!(
+            parse_template("{{ Front }}").unwrap(),
+            vec![TemplateNode::field("Front", &[])]
+        );
+        assert_ne!(
+            parse_template("{{Front}}").unwrap(),
+            vec![TemplateNode::field("Front", &["text"])]
+        );
+    }
+
+    // Printer tests
+    #[test]
+    fn test_print_canonical_template_unchanged() {
+        let template = "<b>{{Front}}</b>{{#Extra}}<br>{{hint:text:Extra}}{{/Extra}}\
+                        {{^Back}}none{{/Back}}";
+        let nodes = parse_template(template).unwrap();
+        assert_eq!(print_template(&nodes), template);
+    }
+
+    #[test]
+    fn test_print_normalizes_tags() {
+        let nodes =
+            parse_template("{{=<% %>=}}<% text : Front %>{{# Extra }}x{{/ Extra }}").unwrap();
+        assert_eq!(print_template(&nodes), "{{text:Front}}{{#Extra}}x{{/Extra}}");
+    }
+
+    #[test]
+    fn test_parse_print_parse_is_identity() {
+        let templates = [
+            "",
+            "plain text with { single } braces and <% percent %>",
+            "{{Front}}\n<hr id=answer>\n{{Back}}",
+            "{{ Front }} {{ text: Notes (EN) }} {{Frage?}} {{Example.1}}",
+            "{{#A}}{{#A}}x{{/A}}{{^B}}{{c:b:a:B}}{{/B}}{{/A}}",
+            "{{=<% %>=}}<%#Extra%><%Extra%><%/Extra%>{{Front}}",
+            "Ünïcödé {{#Feld}}ä{{Feld}}ö{{/Feld}} ü",
+            "{{cloze:Text}}{{type:cloze:Text}}{{FrontSide}}",
+            "{{ =<% %>=}}x",
+            " {{ =<% %>=}}{{=<% %>=}}",
+        ];
+        for template in templates {
+            let nodes = parse_template(template).unwrap();
+            let printed = print_template(&nodes);
+            assert_eq!(parse_template(&printed).unwrap(), nodes, "{}", template);
+        }
+    }
+
+    #[test]
+    fn test_print_built_nodes() {
+        let nodes = vec![
+            TemplateNode::text("Q: "),
+            TemplateNode::conditional(
+                "Extra",
+                false,
+                vec![TemplateNode::field("Extra", &["furigana"])],
+            ),
+        ];
+        assert_eq!(
+            print_template(&nodes),
+            "Q: {{#Extra}}{{furigana:Extra}}{{/Extra}}"
+        );
+    }
+
+    // Compiled template tests
+    #[test]
+    fn test_compiled_template_renders_many_notes() {
+        let compiled = CompiledTemplate::new("{{Front}}{{#Back}} / {{Back}}{{/Back}}").unwrap();
+        let mut fields = HashMap::new();
+        fields.insert("Front".to_string(), "one".to_string());
+        assert_eq!(compiled.render(&fields), "one");
+        fields.insert("Front".to_string(), "two".to_string());
+        fields.insert("Back".to_string(), "zwei".to_string());
+        assert_eq!(compiled.render(&fields), "two / zwei");
+    }
+
+    #[test]
+    fn test_compiled_template_render_cloze() {
+        let compiled = CompiledTemplate::new("{{cloze:Text}}").unwrap();
+        let mut fields = HashMap::new();
+        fields.insert("Text".to_string(), "{{c1::a}} {{c2::b}}".to_string());
+        assert_eq!(
+            compiled.render_cloze(&fields, 2, true),
+            render_with_cloze("{{cloze:Text}}", &fields, 2, true).unwrap()
+        );
+        assert_eq!(
+            compiled.render_cloze(&fields, 2, false),
+            render_with_cloze("{{cloze:Text}}", &fields, 2, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_compiled_template_error() {
+        let err = CompiledTemplate::new("{{#A}}").unwrap_err();
+        assert_eq!(err.kind, TemplateErrorKind::UnclosedBlock);
+    }
+
+    #[test]
+    fn test_compiled_template_nodes() {
+        let compiled = CompiledTemplate::new("a{{B}}").unwrap();
+        assert_eq!(
+            compiled.nodes(),
+            &[TemplateNode::text("a"), TemplateNode::field("B", &[])]
+        );
+    }
+
+    // Field rename tests
+    #[test]
+    fn test_rename_field_everywhere() {
+        let renamed = rename_field(
+            "{{Old}} {{text:Old}} {{#Old}}a{{/Old}}{{^Old}}b{{/Old}} {{Other}}",
+            "Old",
+            "New",
+        )
+        .unwrap();
+        assert_eq!(
+            renamed,
+            "{{New}} {{text:New}} {{#New}}a{{/New}}{{^New}}b{{/New}} {{Other}}"
+        );
+    }
+
+    #[test]
+    fn test_rename_field_leaves_text_untouched() {
+        let renamed = rename_field("Old: {{Old}} (Old)", "Old", "New").unwrap();
+        assert_eq!(renamed, "Old: {{New}} (Old)");
+    }
+
+    #[test]
+    fn test_rename_field_in_nested_blocks() {
+        let renamed =
+            rename_field("{{#A}}{{#Old}}{{cloze:Old}}{{/Old}}{{/A}}", "Old", "Neu (DE)").unwrap();
+        assert_eq!(
+            renamed,
+            "{{#A}}{{#Neu (DE)}}{{cloze:Neu (DE)}}{{/Neu (DE)}}{{/A}}"
+        );
+    }
+
+    #[test]
+    fn test_rename_field_matches_exact_name() {
+        let template = "{{ Olden }}{{old}}{{OldSide}}";
+        assert_eq!(rename_field(template, "Old", "New").unwrap(), template);
+    }
+
+    #[test]
+    fn test_rename_field_normalizes_changed_template() {
+        let renamed = rename_field("{{=<% %>=}}<% Old %>{{ Front }}", "Old", "New").unwrap();
+        assert_eq!(renamed, "{{New}}{{Front}}");
+    }
+
+    #[test]
+    fn test_rename_field_invalid_template() {
+        match rename_field("{{#Old}}", "Old", "New") {
+            Err(RenameFieldError::Template(err)) => {
+                assert_eq!(err.kind, TemplateErrorKind::UnclosedBlock)
+            }
+            other => panic!("expected a template error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rename_field_rejects_invalid_name() {
+        for new in ["", " ", "a:b", "x}}y", "{x", "a\"b", "#x", "^x", "/x", " x"] {
+            assert_eq!(
+                rename_field("{{Old}}", "Old", new),
+                Err(RenameFieldError::InvalidFieldName(new.to_string()))
+            );
+        }
+        assert_eq!(rename_field("{{Old}}", "Old", "a#b/c").unwrap(), "{{a#b/c}}");
+    }
+
+    // Nested conditional tests
+    #[test]
+    fn test_nested_same_field_conditionals() {
+        let nodes = parse_template("{{#A}}{{#A}}x{{/A}}y{{/A}}").unwrap();
+        assert_eq!(
+            nodes,
+            vec![TemplateNode::conditional(
+                "A",
+                false,
+                vec![
+                    TemplateNode::conditional("A", false, vec![TemplateNode::text("x")]),
+                    TemplateNode::text("y"),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_nested_different_field_conditionals() {
+        let mut fields = HashMap::new();
+        fields.insert("A".to_string(), "a".to_string());
+
+        let template = "{{#A}}[{{^B}}no b{{/B}}{{#B}}b{{/B}}]{{/A}}";
+        assert_eq!(render(template, &fields).unwrap(), "[no b]");
+
+        fields.insert("B".to_string(), "b".to_string());
+        assert_eq!(render(template, &fields).unwrap(), "[b]");
+    }
+
+    #[test]
+    fn test_sibling_blocks_same_field() {
+        let mut fields = HashMap::new();
+        fields.insert("A".to_string(), "yes".to_string());
+
+        let result = render("{{#A}}1{{/A}}-{{^A}}2{{/A}}-{{#A}}3{{/A}}", &fields).unwrap();
+        assert_eq!(result, "1--3");
+    }
+
+    #[test]
+    fn test_negated_block_nested_in_same_field() {
+        let fields = HashMap::new();
+
+        let result = render("{{^A}}outer {{^A}}inner{{/A}}{{/A}}", &fields).unwrap();
+        assert_eq!(result, "outer inner");
+    }
+
+    // Block structure error tests
+    #[test]
+    fn test_mismatched_close_reports_both_tags() {
+        let err = parse_template("{{#Front}}\ntext{{/Back}}").unwrap_err();
+        assert_eq!(err.kind, TemplateErrorKind::MismatchedClose);
+        assert_eq!((err.line, err.column, err.offset), (2, 5, 15));
+        assert_eq!(
+            err.open_pos,
+            Some(SourcePos {
+                offset: 0,
+                line: 1,
+                column: 1
+            })
+        );
+        assert_eq!(err.snippet, "{{/Back}}");
+        assert_eq!(
+            err.to_string(),
+            "Parse error at line 2, column 5: close tag {{/Back}} does not match open tag {{#Front}} opened at line 1, column 1"
+        );
+    }
+
+    #[test]
+    fn test_mismatched_close_inside_nested_block() {
+        let err = parse_template("{{#A}}{{^B}}x{{/A}}{{/B}}").unwrap_err();
+        assert!(err
+            .message
+            .contains("close tag {{/A}} does not match open tag {{^B}} opened at line 1, column 7"));
+    }
+
+    #[test]
+    fn test_stray_close_tag() {
+        let err = parse_template("Front: {{/Back}}").unwrap_err();
+        assert_eq!(err.kind, TemplateErrorKind::UnopenedClose);
+        assert_eq!(
+            err.pos(),
+            SourcePos {
+                offset: 7,
+                line: 1,
+                column: 8
+            }
+        );
+        assert_eq!(err.open_pos, None);
+        assert_eq!(
+            err.to_string(),
+            "Parse error at line 1, column 8: close tag {{/Back}} has no matching open tag"
+        );
+    }
+
+    #[test]
+    fn test_block_never_closed() {
+        let err = parse_template("x\n{{#Extra}}more\ntext").unwrap_err();
+        assert_eq!(err.kind, TemplateErrorKind::UnclosedBlock);
+        assert_eq!((err.line, err.column), (2, 1));
+        assert_eq!(err.open_pos, Some(err.pos()));
+        assert_eq!(err.snippet, "{{#Extra}}");
+        assert_eq!(
+            err.message,
+            "block {{#Extra}} is never closed (template ends at line 3, column 5)"
+        );
+    }
+
+    #[test]
+    fn test_first_unclosed_block_reported() {
+        let err = parse_template("{{#A}}{{#B}}x{{/B}}{{#C}}").unwrap_err();
+        assert_eq!(err.kind, TemplateErrorKind::UnclosedBlock);
+        assert_eq!(err.snippet, "{{#A}}");
+    }
+
+    #[test]
+    fn test_syntax_error_fields() {
+        let err = parse_template("Hello\n  {{Name").unwrap_err();
+        assert_eq!(err.kind, TemplateErrorKind::Syntax);
+        assert_eq!((err.offset, err.line, err.column), (8, 2, 3));
+        assert_eq!(err.snippet, "{{Name");
+    }
+
+    // Error recovery tests
+    #[test]
+    fn test_recovering_collects_all_errors() {
+        let template = "{{Front}\n{{#Extra}}{{Extra}}{{/Back}}{{/Extra}}\n{{/Hint}}{{#Tags}}";
+        let (_, errors) = parse_template_recovering(template);
+        let kinds: Vec<_> = errors.iter().map(|e| (e.kind, e.line)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (TemplateErrorKind::Syntax, 1),
+                (TemplateErrorKind::MismatchedClose, 2),
+                (TemplateErrorKind::UnopenedClose, 3),
+                (TemplateErrorKind::UnclosedBlock, 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recovering_skips_broken_tag() {
+        let (nodes, errors) = parse_template_recovering("a{{Bad\"Name}}b{{Good}}");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "invalid tag");
+        assert_eq!(errors[0].snippet, "{{Bad\"Name}}");
+        assert_eq!(
+            nodes,
+            vec![
+                TemplateNode::text("a"),
+                TemplateNode::text("b"),
+                TemplateNode::field("Good", &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recovering_unterminated_tag_keeps_following_text() {
+        let (nodes, errors) = parse_template_recovering("Hello {{Name and {{Other}}");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].column, 7);
+        assert_eq!(errors[0].message, "tag is missing closing '}}'");
+        assert_eq!(
+            nodes,
+            vec![
+                TemplateNode::text("Hello "),
+                TemplateNode::text("Name and "),
+                TemplateNode::field("Other", &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recovering_closes_inner_blocks_on_outer_close() {
+        let (nodes, errors) = parse_template_recovering("{{#A}}{{#B}}x{{/A}}y");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, TemplateErrorKind::MismatchedClose);
+        assert_eq!(
+            nodes,
+            vec![
+                TemplateNode::conditional(
+                    "A",
+                    false,
+                    vec![TemplateNode::conditional("B", false, vec![TemplateNode::text("x")])]
+                ),
+                TemplateNode::text("y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recovering_transposed_close_tags_reported_once() {
+        let (nodes, errors) = parse_template_recovering("{{#A}}{{#B}}x{{/A}}{{/B}}y");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, TemplateErrorKind::MismatchedClose);
+        assert_eq!(errors[0].snippet, "{{/A}}");
+        assert_eq!(
+            nodes,
+            vec![
+                TemplateNode::conditional(
+                    "A",
+                    false,
+                    vec![TemplateNode::conditional("B", false, vec![TemplateNode::text("x")])]
+                ),
+                TemplateNode::text("y"),
+            ]
+        );
+
+        let (_, errors) = parse_template_recovering("{{#X}}{{#A}}{{#B}}{{/A}}{{/B}}{{/X}}{{/B}}");
+        let kinds: Vec<_> = errors.iter().map(|e| (e.kind, e.offset)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (TemplateErrorKind::MismatchedClose, 18),
+                (TemplateErrorKind::UnopenedClose, 36),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recovering_closes_unclosed_blocks_at_end() {
+        let (nodes, errors) = parse_template_recovering("{{^A}}x{{/B}}y");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, TemplateErrorKind::MismatchedClose);
+        assert_eq!(
+            nodes,
+            vec![TemplateNode::conditional(
+                "A",
+                true,
+                vec![TemplateNode::text("x"), TemplateNode::text("y")]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_recovering_valid_template_has_no_errors() {
+        let template = "{{#A}}{{text:A}}{{/A}}";
+        let (nodes, errors) = parse_template_recovering(template);
+        assert!(errors.is_empty());
+        assert_eq!(nodes, parse_template(template).unwrap());
+    }
+
     // Error message tests
     #[test]
     fn test_error_includes_line_column_for_unclosed_conditional() {
         let result = parse_template("{{#Field}");
         assert!(result.is_err());
-        let err = result.unwrap_err();
+        let err = result.unwrap_err().to_string();
         assert!(err.contains("line 1"));
         assert!(err.contains("column"));
     }
@@ -489,7 +1841,7 @@ mod tests {
     fn test_error_position_on_second_line() {
         let result = parse_template("Valid text\n{{#Field}");
         assert!(result.is_err());
-        let err = result.unwrap_err();
+        let err = result.unwrap_err().to_string();
         assert!(err.contains("line 2"));
     }
 
@@ -497,7 +1849,7 @@ mod tests {
     fn test_error_position_for_missing_close_braces() {
         let result = parse_template("Hello {{Name");
         assert!(result.is_err());
-        let err = result.unwrap_err();
+        let err = result.unwrap_err().to_string();
         assert!(err.contains("line 1"));
         assert!(err.contains("column"));
     }
@@ -506,7 +1858,7 @@ mod tests {
     fn test_error_position_for_unclosed_conditional_block() {
         let result = parse_template("{{#Field}}content but no close");
         assert!(result.is_err());
-        let err = result.unwrap_err();
+        let err = result.unwrap_err().to_string();
         assert!(err.contains("line 1"));
     }
 
@@ -515,7 +1867,7 @@ mod tests {
         // Verify the error message follows the expected format
         let result = parse_template("{{#Bad}");
         assert!(result.is_err());
-        let err = result.unwrap_err();
+        let err = result.unwrap_err().to_string();
         // Should match: "Parse error at line X, column Y: description"
         assert!(err.starts_with("Parse error at line "));
     }
@@ -0,0 +1,373 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+/// Line breaks and the tags that act as them
+static LINE_BREAK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)(?:\n|<br\s*/?>|</?div>)+").unwrap());
+
+/// Regex for matching HTML tags
+static HTML_TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]+>").unwrap());
+
+/// A run of characters in a diff, and how it is shown
+#[derive(Debug, Clone, PartialEq)]
+enum DiffToken {
+    /// Typed as expected
+    Good(String),
+    /// Typed but not expected
+    Bad(String),
+    /// Expected but not typed
+    Missed(String),
+}
+
+impl DiffToken {
+    fn to_html(&self) -> String {
+        let (class, text) = match self {
+            DiffToken::Good(text) => ("typeGood", text),
+            DiffToken::Bad(text) => ("typeBad", text),
+            DiffToken::Missed(text) => ("typeMissed", text),
+        };
+        format!(
+            "<span class={}>{}</span>",
+            class,
+            htmlescape::encode_minimal(text)
+        )
+    }
+}
+
+/// One step of turning the typed answer into the expected one
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Keep,
+    /// A typed character that is not in the expected answer
+    Delete,
+    /// An expected character that was not typed
+    Insert,
+}
+
+/// Compare an answer typed by the user with the expected one, producing
+/// Anki's comparison markup.
+///
+/// The expected answer is field content: its HTML is reduced to a single
+/// line of text before comparing. The result is wrapped in
+/// `<code id=typeans>`. If nothing was typed it is just the expected
+/// answer; otherwise it shows the typed answer, with each character marked
+/// `typeGood` or `typeBad`, and, unless the answer is right, an arrow
+/// followed by the expected answer with the characters not typed marked
+/// `typeMissed`. Where characters were left out entirely, the typed
+/// answer shows a `-` for each.
+pub fn compare_answer(expected: &str, typed: &str) -> String {
+    let expected = html_to_text_line(expected);
+    if typed.is_empty() {
+        return format!(
+            "<code id=typeans>{}</code>",
+            htmlescape::encode_minimal(&expected)
+        );
+    }
+
+    let (typed_tokens, expected_tokens) = diff_tokens(typed, &expected);
+    let typed_html: String = typed_tokens.iter().map(DiffToken::to_html).collect();
+    if typed == expected {
+        return format!("<code id=typeans>{}</code>", typed_html);
+    }
+    let expected_html: String = expected_tokens.iter().map(DiffToken::to_html).collect();
+    format!(
+        "<code id=typeans>{}<br><span id=typearrow>&darr;</span><br>{}</code>",
+        typed_html, expected_html
+    )
+}
+
+/// Reduce field content to the plain text a user would type: line breaks
+/// become spaces, tags are removed and entities decoded
+fn html_to_text_line(html: &str) -> String {
+    let text = LINE_BREAK_REGEX.replace_all(html, " ");
+    let text = HTML_TAG_REGEX.replace_all(&text, "");
+    let text = htmlescape::decode_html(&text).unwrap_or_else(|_| text.to_string());
+    text.replace('\u{a0}', " ").trim().to_string()
+}
+
+/// Split the typed and expected answers into runs to show on each side.
+///
+/// Text between two matching runs that was both typed and expected is a
+/// substitution: bad on the typed side and missed on the expected side.
+/// Text only expected is shown as missed on both sides, as dashes on the
+/// typed one.
+fn diff_tokens(typed: &str, expected: &str) -> (Vec<DiffToken>, Vec<DiffToken>) {
+    let typed: Vec<char> = typed.chars().collect();
+    let expected: Vec<char> = expected.chars().collect();
+    let mut typed_tokens = Vec::new();
+    let mut expected_tokens = Vec::new();
+
+    let (mut i, mut j) = (0, 0);
+    let edits = edit_script(&typed, &expected);
+    let mut edits = edits.iter().peekable();
+    while let Some(&edit) = edits.next() {
+        // Gather the run of edits of the same kind (changes together)
+        let mut kept = 0;
+        let (mut deleted, mut inserted) = (0, 0);
+        match edit {
+            Edit::Keep => kept += 1,
+            Edit::Delete => deleted += 1,
+            Edit::Insert => inserted += 1,
+        }
+        while let Some(&&next) = edits.peek() {
+            match (edit, next) {
+                (Edit::Keep, Edit::Keep) => kept += 1,
+                (Edit::Delete | Edit::Insert, Edit::Delete) => deleted += 1,
+                (Edit::Delete | Edit::Insert, Edit::Insert) => inserted += 1,
+                _ => break,
+            }
+            edits.next();
+        }
+
+        if kept > 0 {
+            typed_tokens.push(DiffToken::Good(typed[i..i + kept].iter().collect()));
+            expected_tokens.push(DiffToken::Good(expected[j..j + kept].iter().collect()));
+            i += kept;
+            j += kept;
+            continue;
+        }
+        if deleted > 0 {
+            typed_tokens.push(DiffToken::Bad(typed[i..i + deleted].iter().collect()));
+        } else {
+            typed_tokens.push(DiffToken::Missed("-".repeat(inserted)));
+        }
+        if inserted > 0 {
+            expected_tokens.push(DiffToken::Missed(
+                expected[j..j + inserted].iter().collect(),
+            ));
+        }
+        i += deleted;
+        j += inserted;
+    }
+
+    (typed_tokens, expected_tokens)
+}
+
+/// Largest number of typed × expected characters, after any common prefix
+/// and suffix, that is diffed character by character. Beyond it the
+/// differing middle is shown as wholly wrong, to bound the time spent on
+/// arbitrary input.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// The shortest edits turning `typed` into `expected`, keeping a longest
+/// common subsequence of characters.
+///
+/// Uses Hirschberg's algorithm, so memory is linear in the input length.
+fn edit_script(typed: &[char], expected: &[char]) -> Vec<Edit> {
+    let prefix = typed
+        .iter()
+        .zip(expected)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (typed_rest, expected_rest) = (&typed[prefix..], &expected[prefix..]);
+    let suffix = typed_rest
+        .iter()
+        .rev()
+        .zip(expected_rest.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let typed_mid = &typed_rest[..typed_rest.len() - suffix];
+    let expected_mid = &expected_rest[..expected_rest.len() - suffix];
+
+    let mut edits = Vec::with_capacity(typed.len().max(expected.len()));
+    edits.extend(std::iter::repeat_n(Edit::Keep, prefix));
+    if typed_mid.len().saturating_mul(expected_mid.len()) > MAX_DIFF_CELLS {
+        edits.extend(std::iter::repeat_n(Edit::Delete, typed_mid.len()));
+        edits.extend(std::iter::repeat_n(Edit::Insert, expected_mid.len()));
+    } else {
+        hirschberg(typed_mid, expected_mid, &mut edits);
+    }
+    edits.extend(std::iter::repeat_n(Edit::Keep, suffix));
+    edits
+}
+
+/// Append the edits turning `typed` into `expected` to `edits`, splitting
+/// `typed` in half and `expected` where a longest common subsequence
+/// crosses from one half to the other
+fn hirschberg(typed: &[char], expected: &[char], edits: &mut Vec<Edit>) {
+    if typed.is_empty() {
+        edits.extend(std::iter::repeat_n(Edit::Insert, expected.len()));
+        return;
+    }
+    if expected.is_empty() {
+        edits.extend(std::iter::repeat_n(Edit::Delete, typed.len()));
+        return;
+    }
+    if let [c] = typed {
+        match expected.iter().position(|e| e == c) {
+            Some(j) => {
+                edits.extend(std::iter::repeat_n(Edit::Insert, j));
+                edits.push(Edit::Keep);
+                edits.extend(std::iter::repeat_n(Edit::Insert, expected.len() - j - 1));
+            }
+            None => {
+                edits.push(Edit::Delete);
+                edits.extend(std::iter::repeat_n(Edit::Insert, expected.len()));
+            }
+        }
+        return;
+    }
+
+    let mid = typed.len() / 2;
+    let before = lcs_lengths(&typed[..mid], expected, false);
+    let after = lcs_lengths(&typed[mid..], expected, true);
+    let split = (0..=expected.len())
+        .max_by_key(|&j| (before[j] + after[j], std::cmp::Reverse(j)))
+        .unwrap_or(0);
+    hirschberg(&typed[..mid], &expected[..split], edits);
+    hirschberg(&typed[mid..], &expected[split..], edits);
+}
+
+/// Lengths of the longest common subsequences of `a` with `b[..j]`, for
+/// each `j` from 0 to `b.len()`, or with `b[j..]` if `from_end`
+fn lcs_lengths(a: &[char], b: &[char], from_end: bool) -> Vec<usize> {
+    let m = b.len();
+    let mut row = vec![0; m + 1];
+    let mut prev = vec![0; m + 1];
+    for i in 0..a.len() {
+        let ca = if from_end { a[a.len() - 1 - i] } else { a[i] };
+        std::mem::swap(&mut row, &mut prev);
+        for j in 1..=m {
+            let cb = if from_end { b[m - j] } else { b[j - 1] };
+            row[j] = if ca == cb {
+                prev[j - 1] + 1
+            } else {
+                prev[j].max(row[j - 1])
+            };
+        }
+    }
+    if from_end {
+        // row[j] covers the last j characters of b, i.e. b[m - j..]
+        row.reverse();
+    }
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARROW: &str = "<br><span id=typearrow>&darr;</span><br>";
+
+    #[test]
+    fn test_correct_answer() {
+        assert_eq!(
+            compare_answer("Paris", "Paris"),
+            "<code id=typeans><span class=typeGood>Paris</span></code>"
+        );
+    }
+
+    #[test]
+    fn test_nothing_typed_shows_expected() {
+        assert_eq!(
+            compare_answer("<b>Paris</b> &amp; Lyon", ""),
+            "<code id=typeans>Paris &amp; Lyon</code>"
+        );
+    }
+
+    #[test]
+    fn test_wrong_character() {
+        assert_eq!(
+            compare_answer("Paris", "Parix"),
+            format!(
+                "<code id=typeans><span class=typeGood>Pari</span><span class=typeBad>x</span>\
+                 {}<span class=typeGood>Pari</span><span class=typeMissed>s</span></code>",
+                ARROW
+            )
+        );
+    }
+
+    #[test]
+    fn test_missing_characters_shown_as_dashes() {
+        assert_eq!(
+            compare_answer("Paris", "Pas"),
+            format!(
+                "<code id=typeans><span class=typeGood>Pa</span><span class=typeMissed>--</span>\
+                 <span class=typeGood>s</span>{}<span class=typeGood>Pa</span>\
+                 <span class=typeMissed>ri</span><span class=typeGood>s</span></code>",
+                ARROW
+            )
+        );
+    }
+
+    #[test]
+    fn test_extra_characters() {
+        assert_eq!(
+            compare_answer("cat", "cats"),
+            format!(
+                "<code id=typeans><span class=typeGood>cat</span><span class=typeBad>s</span>\
+                 {}<span class=typeGood>cat</span></code>",
+                ARROW
+            )
+        );
+    }
+
+    #[test]
+    fn test_expected_html_reduced_to_text() {
+        assert_eq!(
+            compare_answer("<div>a&nbsp;b</div><div>c<br>d</div>\n", "a b c d"),
+            "<code id=typeans><span class=typeGood>a b c d</span></code>"
+        );
+    }
+
+    #[test]
+    fn test_typed_answer_is_escaped() {
+        assert_eq!(
+            compare_answer("a", "<b>"),
+            format!(
+                "<code id=typeans><span class=typeBad>&lt;b&gt;</span>{}\
+                 <span class=typeMissed>a</span></code>",
+                ARROW
+            )
+        );
+    }
+
+    #[test]
+    fn test_edit_script_keeps_longest_common_subsequence() {
+        for (typed, expected, kept) in [
+            ("kitten", "sitting", 4),
+            ("abcbdab", "bdcaba", 4),
+            ("x", "abc", 0),
+            ("", "abc", 0),
+            ("the quick brown fox", "a quick brown dog", 14),
+        ] {
+            let typed: Vec<char> = typed.chars().collect();
+            let expected: Vec<char> = expected.chars().collect();
+            let edits = edit_script(&typed, &expected);
+            let count = |kind| edits.iter().filter(|&&e| e == kind).count();
+            assert_eq!(count(Edit::Keep), kept);
+            assert_eq!(count(Edit::Keep) + count(Edit::Delete), typed.len());
+            assert_eq!(count(Edit::Keep) + count(Edit::Insert), expected.len());
+        }
+    }
+
+    #[test]
+    fn test_long_answers_diffed_around_common_ends() {
+        let expected = "a".repeat(5000) + "b" + &"c".repeat(5000);
+        let typed = "a".repeat(5000) + "x" + &"c".repeat(5000);
+        let (typed_tokens, _) = diff_tokens(&typed, &expected);
+        assert_eq!(typed_tokens[1], DiffToken::Bad("x".into()));
+    }
+
+    #[test]
+    fn test_huge_difference_shown_as_wrong() {
+        let typed = "ab".repeat(1100);
+        let expected = "ba".repeat(1100);
+        let (typed_tokens, expected_tokens) = diff_tokens(&typed, &expected);
+        assert_eq!(typed_tokens, vec![DiffToken::Bad(typed)]);
+        assert_eq!(expected_tokens, vec![DiffToken::Missed(expected)]);
+    }
+
+    #[test]
+    fn test_diff_counts_characters_not_bytes() {
+        let (typed, expected) = diff_tokens("über", "uber");
+        assert_eq!(
+            typed,
+            vec![DiffToken::Bad("ü".into()), DiffToken::Good("ber".into())]
+        );
+        assert_eq!(
+            expected,
+            vec![DiffToken::Missed("u".into()), DiffToken::Good("ber".into())]
+        );
+    }
+}